@@ -1,31 +1,451 @@
-use clap::Parser;
-use deez::{standard::StandardNotation, Notation, Roll};
+use clap::{Parser, Subcommand, ValueEnum};
+use colored::Colorize;
+use deez::{
+    dnd, fate, shadowrun, simulate, standard::StandardNotation, wod, Environment, Error, Notation,
+    Repeat, RollError, TryRoll, MAX_REPEATS, Z_95,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::Serialize;
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Output only the result total
     #[arg(short, long, default_value_t = false)]
     simple: bool,
 
-    /// Dice rolls of the format [A]dB[RET][MOD]
+    /// Also describe totals on the Fate ladder, such as "Good (+3)"
+    #[arg(short, long, default_value_t = false)]
+    ladder: bool,
+
+    /// Output format of roll results
+    #[arg(short, long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+
+    /// Seed the dice for reproducible rolls
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Bind variables such as @str to the numbers on a TOML or JSON character sheet
+    #[arg(long)]
+    sheet: Option<PathBuf>,
+
+    /// Roll critical damage, doubling the number of dice but not the modifiers
+    #[arg(short, long, default_value_t = false)]
+    crit: bool,
+
+    /// Roll each argument this many times, up to 1000 rolls in all
+    #[arg(short = 'n', long, default_value_t = 1)]
+    times: usize,
+
+    /// Order repeated rolls from highest to lowest total
+    #[arg(long, default_value_t = false)]
+    sort: bool,
+
+    /// Also print the sum of repeated rolls
+    #[arg(long, default_value_t = false)]
+    sum: bool,
+
+    /// Dice rolls of the format [A]dB[RET][MOD], combined with + - x / and ( )
     rolls: Vec<String>,
 }
 
-fn main() -> Result<(), std::io::Error> {
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// Colored, human readable results
+    Text,
+    /// A single JSON array of every result
+    Json,
+    /// One JSON object per line for each result
+    Ndjson,
+}
+
+#[derive(clap::Args, Debug)]
+struct Thresholds {
+    /// Also print the chance of rolling at least this total
+    #[arg(long, allow_hyphen_values = true)]
+    at_least: Option<isize>,
+
+    /// Also print the chance of rolling at most this total
+    #[arg(long, allow_hyphen_values = true)]
+    at_most: Option<isize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show the probability distribution of rolls
+    Stats {
+        #[command(flatten)]
+        thresholds: Thresholds,
+
+        /// Bind variables such as @str to the numbers on a TOML or JSON character sheet
+        #[arg(long)]
+        sheet: Option<PathBuf>,
+
+        /// Dice rolls of the format [A]dB[RET][MOD], combined with + - x / and ( )
+        rolls: Vec<String>,
+    },
+    /// Estimate the distribution of rolls by rolling them many times
+    Sim {
+        /// Number of times to roll each expression
+        #[arg(short, default_value_t = 100_000)]
+        n: u64,
+
+        /// Seed the dice for a reproducible simulation
+        #[arg(long)]
+        seed: Option<u64>,
+
+        #[command(flatten)]
+        thresholds: Thresholds,
+
+        /// Bind variables such as @str to the numbers on a TOML or JSON character sheet
+        #[arg(long)]
+        sheet: Option<PathBuf>,
+
+        /// Dice rolls of the format [A]dB[RET][MOD], combined with + - x / and ( )
+        rolls: Vec<String>,
+    },
+    /// Roll World of Darkness dice pools
+    Wod {
+        /// Output format of roll results
+        #[arg(short, long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Seed the dice for reproducible rolls
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Dice pools of the format AwodB[r], for A dice with B-again, rolled as a rote
+        /// with r, or a chance die when A is 0
+        rolls: Vec<String>,
+    },
+    /// Roll Shadowrun dice pools
+    #[command(alias = "sr")]
+    Shadowrun {
+        /// Output format of roll results
+        #[arg(short, long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Seed the dice for reproducible rolls
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Dice pools of the format AsrL[!], for A dice with a limit of L, using Edge
+        /// with !
+        rolls: Vec<String>,
+    },
+}
+
+fn report(input: &str, error: Error) -> ExitCode {
+    match error {
+        Error::Parse(e) => eprintln!("{}\n{}", "error: invalid roll".red().bold(), e.render()),
+        Error::Roll(e) => eprintln!("{} {}: {}", "error: cannot roll".red().bold(), input, e),
+        e => eprintln!("{} {}", "error:".red().bold(), e),
+    }
+    ExitCode::FAILURE
+}
+
+fn rng(seed: Option<u64>) -> Box<dyn RngCore> {
+    match seed {
+        Some(seed) => Box::new(StdRng::seed_from_u64(seed)),
+        None => Box::new(rand::thread_rng()),
+    }
+}
+
+/// Loads the numbers on a TOML or JSON character sheet, naming nested values by their
+/// path, such as `saves.dex`. Anything that is not a whole number is ignored.
+fn sheet(path: Option<&Path>) -> Result<Environment, String> {
+    fn bind(prefix: Option<&str>, value: &serde_json::Value, env: &mut Environment) {
+        match value {
+            serde_json::Value::Object(table) => {
+                for (key, value) in table {
+                    let name = match prefix {
+                        Some(prefix) => format!("{}.{}", prefix, key),
+                        None => key.clone(),
+                    };
+                    bind(Some(&name), value, env);
+                }
+            }
+            serde_json::Value::Number(n) => {
+                if let (Some(prefix), Some(n)) = (prefix, n.as_i64()) {
+                    if let Ok(n) = isize::try_from(n) {
+                        env.insert(prefix.to_string(), n);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut env = Environment::new();
+    let Some(path) = path else {
+        return Ok(env);
+    };
+
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    let value: serde_json::Value = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        _ => toml::from_str(&text).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| format!("cannot load {}: {}", path.display(), e))?;
+
+    bind(None, &value, &mut env);
+    Ok(env)
+}
+
+/// Parses and rolls each input with `roll`, printing the results in `format`.
+fn pools<T, F>(rolls: Vec<String>, seed: Option<u64>, format: Format, roll: F) -> ExitCode
+where
+    T: Display + Serialize,
+    F: Fn(&str, &mut dyn RngCore) -> Result<T, Error>,
+{
+    let mut rng = rng(seed);
+    let mut results = Vec::new();
+
+    for input in rolls {
+        let result = match roll(&input, rng.as_mut()) {
+            Ok(result) => result,
+            Err(e) => return report(&input, e),
+        };
+
+        match format {
+            Format::Text => println!("{}", result),
+            Format::Json => results.push(result),
+            Format::Ndjson => println!("{}", serde_json::to_string(&result).unwrap()),
+        }
+    }
+
+    if format == Format::Json {
+        println!("{}", serde_json::to_string(&results).unwrap());
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn stats(rolls: Vec<String>, thresholds: Thresholds, env: &Environment) -> ExitCode {
+    for input in rolls {
+        let repeats = match StandardNotation::parse_repeats(&input) {
+            Ok(repeats) => repeats,
+            Err(e) => return report(&input, e),
+        };
+
+        for Repeat { expression: r, .. } in repeats {
+            let distribution = match r
+                .bind(env)
+                .map_err(Error::from)
+                .and_then(|b| b.distribution())
+            {
+                Ok(distribution) => distribution,
+                Err(e) => return report(&input, e),
+            };
+
+            println!(
+                "{:<10}: mean {:.2}, stdev {:.2}, range {}..{}",
+                r.to_string(),
+                distribution.mean(),
+                distribution.std_dev(),
+                distribution.min(),
+                distribution.max()
+            );
+            print!("{}", distribution);
+            if let Some(n) = thresholds.at_least {
+                let label = format!("P(>= {})", n);
+                println!("{:<10}: {:.2}%", label, distribution.at_least(n) * 100.0);
+            }
+            if let Some(n) = thresholds.at_most {
+                let label = format!("P(<= {})", n);
+                println!("{:<10}: {:.2}%", label, distribution.at_most(n) * 100.0);
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn sim(
+    rolls: Vec<String>,
+    n: u64,
+    seed: Option<u64>,
+    thresholds: Thresholds,
+    env: &Environment,
+) -> ExitCode {
+    let mut rng = rng(seed);
+
+    for input in rolls {
+        let repeats = match StandardNotation::parse_repeats(&input) {
+            Ok(repeats) => repeats,
+            Err(e) => return report(&input, e),
+        };
+
+        for Repeat { expression: r, .. } in repeats {
+            let simulation = match r.bind(env).and_then(|b| simulate(&b, n, rng.as_mut())) {
+                Ok(simulation) => simulation,
+                Err(e) => return report(&input, e.into()),
+            };
+
+            let (low, high) = simulation.confidence_interval(Z_95);
+            println!(
+                "{:<10}: mean {:.2} (95% CI {:.2}..{:.2}), stdev {:.2}, range {}..{}",
+                r.to_string(),
+                simulation.mean(),
+                low,
+                high,
+                simulation.std_dev(),
+                simulation.min(),
+                simulation.max()
+            );
+            print!("{}", simulation.distribution());
+
+            let queries = [
+                thresholds
+                    .at_least
+                    .map(|n| (">=", n, simulation.at_least(n))),
+                thresholds.at_most.map(|n| ("<=", n, simulation.at_most(n))),
+            ];
+            for (op, n, p) in queries.into_iter().flatten() {
+                let (low, high) = simulation.probability_interval(p, Z_95);
+                let label = format!("P({} {})", op, n);
+                println!(
+                    "{:<10}: {:.2}% (95% CI {:.2}%..{:.2}%)",
+                    label,
+                    p * 100.0,
+                    low * 100.0,
+                    high * 100.0
+                );
+            }
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
+    let path = match &args.command {
+        Some(Command::Stats { sheet, .. } | Command::Sim { sheet, .. }) => sheet,
+        Some(Command::Wod { .. } | Command::Shadowrun { .. }) => &None,
+        None => &args.sheet,
+    };
+    let env = match sheet(path.as_deref()) {
+        Ok(env) => env,
+        Err(e) => {
+            eprintln!("{} {}", "error:".red().bold(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match args.command {
+        Some(Command::Stats {
+            thresholds, rolls, ..
+        }) => return stats(rolls, thresholds, &env),
+        Some(Command::Sim {
+            n,
+            seed,
+            thresholds,
+            rolls,
+            ..
+        }) => return sim(rolls, n, seed, thresholds, &env),
+        Some(Command::Wod {
+            format,
+            seed,
+            rolls,
+        }) => {
+            return pools(rolls, seed, format, |input, rng| {
+                Ok(input.parse::<wod::Pool>()?.try_roll_with(rng)?)
+            })
+        }
+        Some(Command::Shadowrun {
+            format,
+            seed,
+            rolls,
+        }) => {
+            return pools(rolls, seed, format, |input, rng| {
+                Ok(input.parse::<shadowrun::Pool>()?.try_roll_with(rng)?)
+            })
+        }
+        None => {}
+    }
+
+    let mut rng = rng(args.seed);
+    let mut results = Vec::new();
+
     for input in args.rolls {
-        let rolls = StandardNotation::parse_from_str(&input)?;
+        let repeats = match StandardNotation::parse_repeats(&input) {
+            Ok(repeats) => repeats,
+            Err(e) => return report(&input, e),
+        };
+
+        for repeat in repeats {
+            let expression = match args.crit {
+                true => dnd::critical(&repeat.expression),
+                false => repeat.expression,
+            };
+            let times = match repeat.count.checked_mul(args.times) {
+                Some(times) if times <= MAX_REPEATS => times,
+                _ => {
+                    let error = RollError::TooManyRepeats {
+                        count: repeat.count.saturating_mul(args.times),
+                        max: MAX_REPEATS,
+                    };
+                    return report(&input, error.into());
+                }
+            };
+            let mut rolls = Vec::with_capacity(times);
+            for _ in 0..times {
+                match expression.try_roll_in(&env, rng.as_mut()) {
+                    Ok(result) => rolls.push(result),
+                    Err(e) => return report(&input, e.into()),
+                }
+            }
+            if args.sort {
+                rolls.sort_by_key(|r| std::cmp::Reverse(r.total));
+            }
+            let sum = match args.sum && rolls.len() > 1 && args.format == Format::Text {
+                true => match rolls.iter().try_fold(0isize, |a, r| a.checked_add(r.total)) {
+                    Some(sum) => Some(sum),
+                    None => return report(&input, RollError::Overflow.into()),
+                },
+                false => None,
+            };
+
+            for result in rolls {
+                match args.format {
+                    Format::Text if args.simple && args.ladder => {
+                        println!("{}", fate::describe(result.total))
+                    }
+                    Format::Text if args.simple => println!("{}", result.total),
+                    Format::Text if args.ladder => {
+                        println!("{} {}", result, fate::describe(result.total))
+                    }
+                    Format::Text => println!("{}", result),
+                    Format::Json => results.push(result),
+                    Format::Ndjson => println!("{}", serde_json::to_string(&result).unwrap()),
+                }
+            }
 
-        for mut r in rolls {
-            if args.simple {
-                println!("{}", r.roll().total);
-            } else {
-                println!("{}", r.roll());
+            if let Some(sum) = sum {
+                match args.simple {
+                    true => println!("{}", sum),
+                    false => println!("{:<10}: {}", "sum", sum),
+                }
             }
         }
     }
 
-    Ok(())
+    if args.format == Format::Json {
+        println!("{}", serde_json::to_string(&results).unwrap());
+    }
+
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,960 @@
+use super::*;
+use std::{collections::BTreeMap, ops::Range};
+
+/// The most work, roughly in floating point operations, a single step of the
+/// distribution engine may take before giving up.
+const MAX_WORK: usize = 100_000_000;
+
+/// The most faces a die may have to have its distribution listed.
+const MAX_FACES: usize = 1_000_000;
+
+/// Explosions are followed until the chance of exploding again drops below this.
+const EXPLODE_EPSILON: f64 = 1e-12;
+
+/// Histograms with more outcomes than this group them into ranges.
+const HISTOGRAM_ROWS: usize = 40;
+const HISTOGRAM_WIDTH: usize = 50;
+
+/// The exact probability of every outcome of a [`RollExpression`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    pub(crate) outcomes: BTreeMap<isize, f64>,
+}
+
+impl Distribution {
+    fn constant(value: isize) -> Self {
+        Distribution {
+            outcomes: BTreeMap::from([(value, 1.0)]),
+        }
+    }
+
+    fn uniform(values: impl Iterator<Item = isize>) -> Self {
+        let values = values.collect::<Vec<isize>>();
+        let p = 1.0 / values.len() as f64;
+        let mut outcomes = BTreeMap::new();
+        for v in values {
+            *outcomes.entry(v).or_insert(0.0) += p;
+        }
+        Distribution { outcomes }
+    }
+
+    /// Iterates over every possible outcome and its probability, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (isize, f64)> + '_ {
+        self.outcomes.iter().map(|(v, p)| (*v, *p))
+    }
+
+    pub fn len(&self) -> usize {
+        self.outcomes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outcomes.is_empty()
+    }
+
+    pub fn probability(&self, value: isize) -> f64 {
+        self.outcomes.get(&value).copied().unwrap_or(0.0)
+    }
+
+    pub fn at_least(&self, value: isize) -> f64 {
+        self.outcomes.range(value..).map(|(_, p)| p).sum()
+    }
+
+    pub fn at_most(&self, value: isize) -> f64 {
+        self.outcomes.range(..=value).map(|(_, p)| p).sum()
+    }
+
+    pub fn min(&self) -> isize {
+        self.outcomes.keys().next().copied().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> isize {
+        self.outcomes.keys().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(v, p)| v as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.iter()
+            .map(|(v, p)| (v as f64 - mean).powi(2) * p)
+            .sum()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The smallest outcome that at least `p` percent of rolls will not exceed.
+    pub fn percentile(&self, p: f64) -> isize {
+        let target = (p / 100.0).clamp(0.0, 1.0);
+        let mut cumulative = 0.0;
+        for (v, q) in self.iter() {
+            cumulative += q;
+            // Allow for rounding error accumulated while summing.
+            if cumulative >= target - 1e-12 {
+                return v;
+            }
+        }
+        self.max()
+    }
+
+    fn map(&self, f: impl Fn(isize) -> Result<isize, RollError>) -> Result<Self, RollError> {
+        let mut outcomes = BTreeMap::new();
+        for (v, p) in self.iter() {
+            *outcomes.entry(f(v)?).or_insert(0.0) += p;
+        }
+        Ok(Distribution { outcomes })
+    }
+
+    fn combine(
+        &self,
+        other: &Distribution,
+        f: impl Fn(isize, isize) -> Result<isize, RollError>,
+    ) -> Result<Self, Error> {
+        check_work(self.len().saturating_mul(other.len()), "combining terms")?;
+
+        let mut outcomes = BTreeMap::new();
+        for (a, p) in self.iter() {
+            for (b, q) in other.iter() {
+                *outcomes.entry(f(a, b)?).or_insert(0.0) += p * q;
+            }
+        }
+        Ok(Distribution { outcomes })
+    }
+
+    /// The distribution of the sum of `count` independent rolls of `self`.
+    fn repeat(&self, count: usize) -> Result<Self, Error> {
+        // The sum has no more outcomes than the values in its range, nor than the ways
+        // of rolling the dice.
+        let range = self.max().abs_diff(self.min());
+        let sums = count.saturating_mul(range).saturating_add(1);
+        let sums = self
+            .len()
+            .checked_pow(count.try_into().unwrap_or(u32::MAX))
+            .map_or(sums, |ways| ways.min(sums));
+        check_work(
+            count
+                .saturating_sub(1)
+                .saturating_mul(sums)
+                .saturating_mul(self.len()),
+            "summing dice",
+        )?;
+
+        // A single die needs no summing.
+        if count == 0 {
+            return Ok(Distribution::constant(0));
+        }
+        let mut total = self.clone();
+        for _ in 1..count {
+            total = total.combine(self, |a, b| Operator::Add.apply(a, b))?;
+        }
+        Ok(total)
+    }
+
+    /// The distribution of the highest (or lowest) of independent rolls of each of
+    /// `distributions`.
+    fn extreme(distributions: &[Distribution], highest: bool) -> Self {
+        let mut values = distributions
+            .iter()
+            .flat_map(|d| d.outcomes.keys().copied())
+            .collect::<Vec<isize>>();
+        values.sort();
+        values.dedup();
+        if !highest {
+            values.reverse();
+        }
+
+        // The chance that every roll is no better than each value, accumulated in order.
+        let mut outcomes = BTreeMap::new();
+        let mut previous = 0.0;
+        for v in values {
+            let cumulative = distributions
+                .iter()
+                .map(|d| if highest { d.at_most(v) } else { d.at_least(v) })
+                .product::<f64>();
+            if cumulative > previous {
+                outcomes.insert(v, cumulative - previous);
+            }
+            previous = cumulative;
+        }
+        Distribution { outcomes }
+    }
+
+    /// The distribution of the sum of `count` independent rolls of `self`, keeping only
+    /// the dice ranked `kept.start` up to `kept.end` when sorted from lowest to highest.
+    ///
+    /// Faces are assigned to dice from the lowest to the highest value, so the dice
+    /// assigned at ranks within `kept` are exactly the ones retained. Each step chooses
+    /// how many of the remaining dice show the current face.
+    fn keep(&self, count: usize, kept: Range<usize>) -> Result<Self, Error> {
+        let range = self.max().abs_diff(self.min());
+        check_work(
+            self.len()
+                .saturating_mul(count)
+                .saturating_mul(count)
+                .saturating_mul(kept.len().saturating_mul(range).saturating_add(1)),
+            "keeping dice",
+        )?;
+
+        let faces = self.iter().collect::<Vec<(isize, f64)>>();
+        let mut states: Vec<BTreeMap<isize, f64>> = vec![BTreeMap::new(); count + 1];
+        states[0].insert(0, 1.0);
+
+        for (value, p) in faces {
+            let mut next: Vec<BTreeMap<isize, f64>> = vec![BTreeMap::new(); count + 1];
+            for (assigned, sums) in states.iter().enumerate() {
+                let remaining = count - assigned;
+                // C(remaining, n) * p^n, built up incrementally.
+                let mut weight = 1.0;
+                for n in 0..=remaining {
+                    if n > 0 {
+                        weight *= (remaining - n + 1) as f64 / n as f64 * p;
+                    }
+                    let kept = (assigned + n)
+                        .min(kept.end)
+                        .saturating_sub(assigned.max(kept.start))
+                        as isize;
+                    for (sum, q) in sums.iter() {
+                        let sum = kept
+                            .checked_mul(value)
+                            .and_then(|k| k.checked_add(*sum))
+                            .ok_or(RollError::Overflow)?;
+                        *next[assigned + n].entry(sum).or_insert(0.0) += q * weight;
+                    }
+                }
+            }
+            states = next;
+        }
+
+        Ok(Distribution {
+            outcomes: states.swap_remove(count),
+        })
+    }
+}
+
+impl Display for Distribution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use colored::{ColoredString, Colorize};
+
+        let rows: Vec<(String, f64)> = if self.len() <= HISTOGRAM_ROWS {
+            self.iter().map(|(v, p)| (v.to_string(), p)).collect()
+        } else {
+            // Buckets are worked out in i128, as the span of outcomes may not fit an isize.
+            let (min, max) = (self.min() as i128, self.max() as i128);
+            let rows = HISTOGRAM_ROWS as i128;
+            let size = (max - min + rows) / rows;
+            (0..rows)
+                .map(|i| min + i * size)
+                .take_while(|start| *start <= max)
+                .map(|start| {
+                    let end = (start + size - 1).min(max);
+                    let (start, end) = (start as isize, end as isize);
+                    let p = self
+                        .outcomes
+                        .range(start..=end)
+                        .fold(0.0, |a, (_, p)| a + p);
+                    if start == end {
+                        (start.to_string(), p)
+                    } else {
+                        (format!("{}..{}", start, end), p)
+                    }
+                })
+                .collect()
+        };
+
+        let label_width = rows.iter().map(|(l, _)| l.len()).max().unwrap_or(0);
+        let peak = rows.iter().map(|(_, p)| *p).fold(0.0, f64::max);
+
+        for (i, (label, p)) in rows.iter().enumerate() {
+            let width = if *p > 0.0 {
+                ((p / peak * HISTOGRAM_WIDTH as f64).round() as usize).max(1)
+            } else {
+                0
+            };
+            let mut bar: ColoredString = "#".repeat(width).normal();
+            if i == 0 {
+                bar = bar.red();
+            } else if i == rows.len() - 1 {
+                bar = bar.green();
+            }
+
+            writeln!(
+                f,
+                "{:>w$} {:>6.2}% {}",
+                label,
+                p * 100.0,
+                bar,
+                w = label_width
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_work(work: usize, what: &str) -> Result<(), Error> {
+    if work > MAX_WORK {
+        Err(Error::Intractable(format!("{} is too expensive", what)))
+    } else {
+        Ok(())
+    }
+}
+
+impl Dice {
+    fn face_distribution(&self) -> Result<Distribution, Error> {
+        if self.faces.sides() > MAX_FACES {
+            return Err(Error::Intractable(
+                "listing faces is too expensive".to_string(),
+            ));
+        }
+
+        let faces = match &self.faces {
+            Faces::Custom(faces) => Distribution::uniform(faces.iter().map(Face::value)),
+            faces => Distribution::uniform(faces.min()..=faces.max()),
+        };
+
+        // Rerolling until a face stops matching leaves the other faces in proportion.
+        let settled = faces
+            .iter()
+            .filter(|(v, _)| !self.rerolls(*v, false))
+            .collect::<Vec<(isize, f64)>>();
+        let total = settled.iter().map(|(_, p)| p).sum::<f64>();
+        let faces = Distribution {
+            outcomes: settled.into_iter().map(|(v, p)| (v, p / total)).collect(),
+        };
+
+        // A single reroll is another settled roll, taken in place of the matching face.
+        let rerolled = faces
+            .iter()
+            .filter(|(v, _)| self.rerolls(*v, true))
+            .map(|(_, p)| p)
+            .sum::<f64>();
+        Ok(Distribution {
+            outcomes: faces
+                .iter()
+                .map(|(v, p)| {
+                    let kept = if self.rerolls(v, true) { 0.0 } else { p };
+                    (v, kept + rerolled * p)
+                })
+                .filter(|(_, p)| *p > 0.0)
+                .collect(),
+        })
+    }
+
+    /// The distribution of a single die, including any chain of explosions. Explosions
+    /// are followed until the chance of another is negligible or the limit is reached,
+    /// then the final roll is treated as not exploding. A compounded die totals the same
+    /// as the chain of dice it replaces.
+    ///
+    /// When counting successes, the outcome is the number of successes instead.
+    fn die_distribution(&self) -> Result<Distribution, Error> {
+        let faces = self.face_distribution()?;
+        let counts = self.target().is_some();
+        let successes = |v: isize| Ok(self.successes(v));
+        let Some((compare, kind, limit)) = self.explosion() else {
+            return Ok(if counts { faces.map(successes)? } else { faces });
+        };
+
+        // Compounded dice are counted once, by their total, and other dice one by one.
+        let each = counts && kind != Explosion::Compound;
+        let worth = |v: isize| if each { self.successes(v) } else { v };
+
+        let p = faces
+            .iter()
+            .filter(|(v, _)| compare.matches(*v))
+            .map(|(_, p)| p)
+            .sum::<f64>();
+        // Dice that always explode only stop at the limit.
+        let depth = if p >= 1.0 {
+            limit
+        } else {
+            ((EXPLODE_EPSILON.ln() / p.ln()).ceil() as usize).min(limit)
+        };
+        if depth == 0 {
+            return Ok(faces.map(|v| Ok(worth(v)))?);
+        }
+        check_work(
+            depth
+                .saturating_mul(depth)
+                .saturating_mul(faces.len())
+                .saturating_mul(faces.len()),
+            "following explosions",
+        )?;
+
+        // Rolls `faces` once, less `penalty`, followed by `chain` if the roll explodes.
+        let step = |penalty: isize, chain: &Distribution| -> Result<Distribution, Error> {
+            let mut outcomes = BTreeMap::new();
+            for (face, p) in faces.iter() {
+                let v = worth(face.checked_sub(penalty).ok_or(RollError::Overflow)?);
+                if !compare.matches(face) {
+                    *outcomes.entry(v).or_insert(0.0) += p;
+                    continue;
+                }
+                for (w, q) in chain.iter() {
+                    let total = v.checked_add(w).ok_or(RollError::Overflow)?;
+                    *outcomes.entry(total).or_insert(0.0) += p * q;
+                }
+            }
+            Ok(Distribution { outcomes })
+        };
+
+        // Penetrating explosions subtract one from every die after the first.
+        let penalty = isize::from(kind == Explosion::Penetrate);
+        let mut chain =
+            faces.map(|v| Ok(worth(v.checked_sub(penalty).ok_or(RollError::Overflow)?)))?;
+        for _ in 1..depth {
+            chain = step(penalty, &chain)?;
+        }
+        let die = step(0, &chain)?;
+
+        if counts && !each {
+            Ok(die.map(successes)?)
+        } else {
+            Ok(die)
+        }
+    }
+
+    fn distribution(&self) -> Result<Distribution, Error> {
+        let die = self.die_distribution()?;
+
+        let (low, high) = self.retention.dropped(self.count);
+        if low + high == 0 {
+            die.repeat(self.count)
+        } else if self.target().is_some() {
+            Err(Error::Intractable(
+                "keeping some of a pool of dice counting successes is not supported".to_string(),
+            ))
+        } else if matches!(self.explosion(), Some((_, kind, _)) if kind != Explosion::Compound) {
+            Err(Error::Intractable(
+                "keeping some of a pool of exploding dice is not supported".to_string(),
+            ))
+        } else {
+            die.keep(self.count, low..self.count - high)
+        }
+    }
+}
+
+impl NestedDice {
+    /// Weighs the distribution of the dice for every count and number of sides by the
+    /// chance of rolling them.
+    fn distribution(&self) -> Result<Distribution, Error> {
+        let rolled = |expression: &Option<Box<RollExpression>>| match expression {
+            Some(e) => Ok(e
+                .distribution_unchecked()?
+                .iter()
+                .map(|(v, p)| (Some(v), p))
+                .collect()),
+            None => Ok::<_, Error>(vec![(None, 1.0)]),
+        };
+        let counts = rolled(&self.count)?;
+        let sides = rolled(&self.sides)?;
+        check_work(
+            counts.len() * sides.len(),
+            "rolling dice for the count and sides",
+        )?;
+
+        // Work is charged across every count and number of sides, since each is weighed
+        // separately.
+        let most = match counts.last() {
+            Some((Some(count), _)) => usize::try_from(*count).unwrap_or(0),
+            _ => self.dice.count,
+        };
+        let mut work = 0usize;
+        let mut charge = |amount: usize| {
+            work = work.saturating_add(amount);
+            check_work(work, "rolling dice for the count and sides")
+        };
+
+        let mut outcomes = BTreeMap::new();
+        let mut weigh = |d: &Distribution, p: f64| {
+            for (v, q) in d.iter() {
+                *outcomes.entry(v).or_insert(0.0) += p * q;
+            }
+        };
+        for (sides, q) in sides.iter() {
+            // Dice keeping every die are summed one die at a time, from the fewest dice
+            // rolled to the most, rather than from scratch for every count.
+            let mut die = None;
+            let mut sum = Distribution::constant(0);
+            let mut summed = 0;
+            for (count, p) in counts.iter() {
+                let dice = self.resolve(*count, *sides)?;
+                let die = match die {
+                    Some(ref die) => die,
+                    None => {
+                        let d = dice.die_distribution()?;
+                        let range = d.max().abs_diff(d.min());
+                        charge(
+                            most.saturating_mul(most)
+                                .saturating_mul(range.saturating_add(1))
+                                .saturating_mul(d.len()),
+                        )?;
+                        die.insert(d)
+                    }
+                };
+                if dice.retention.dropped(dice.count) != (0, 0) {
+                    let range = die.max().abs_diff(die.min());
+                    charge(
+                        die.len()
+                            .saturating_mul(dice.count)
+                            .saturating_mul(dice.count)
+                            .saturating_mul(dice.count.saturating_mul(range).saturating_add(1)),
+                    )?;
+                    weigh(&dice.distribution()?, p * q);
+                    continue;
+                }
+                while summed < dice.count {
+                    sum = sum.combine(die, |a, b| Operator::Add.apply(a, b))?;
+                    summed += 1;
+                }
+                weigh(&sum, p * q);
+            }
+        }
+        Ok(Distribution { outcomes })
+    }
+}
+
+impl Conditional {
+    /// Weighs the distributions of the branches by the chance the condition passes.
+    fn distribution(&self) -> Result<Distribution, Error> {
+        let condition = self.condition.distribution_unchecked()?;
+        let passed = match &self.comparison {
+            Some((comparison, rhs)) => condition
+                .combine(&rhs.distribution_unchecked()?, |a, b| {
+                    Ok(comparison.matches(a, b) as isize)
+                })?
+                .probability(1),
+            None => 1.0 - condition.probability(0),
+        };
+
+        let mut outcomes = BTreeMap::new();
+        for (branch, p) in [(&self.then, passed), (&self.otherwise, 1.0 - passed)] {
+            if p > 0.0 {
+                for (v, q) in branch.distribution_unchecked()?.iter() {
+                    *outcomes.entry(v).or_insert(0.0) += p * q;
+                }
+            }
+        }
+        Ok(Distribution { outcomes })
+    }
+}
+
+impl Group {
+    fn distribution(&self) -> Result<Distribution, Error> {
+        let members = self
+            .members
+            .iter()
+            .map(|m| m.distribution_unchecked())
+            .collect::<Result<Vec<Distribution>, Error>>()?;
+
+        match self.retention.dropped(members.len()) {
+            (0, 0) => members
+                .iter()
+                .try_fold(Distribution::constant(0), |total, m| {
+                    total.combine(m, |a, b| Operator::Add.apply(a, b))
+                }),
+            (low, 0) if low + 1 == members.len() => Ok(Distribution::extreme(&members, true)),
+            (0, high) if high + 1 == members.len() => Ok(Distribution::extreme(&members, false)),
+            _ => Err(Error::Intractable(
+                "keeping more than one member of a group is not supported".to_string(),
+            )),
+        }
+    }
+}
+
+impl Function {
+    fn distribution(&self, args: &[RollExpression]) -> Result<Distribution, Error> {
+        match (self.rounding(), args) {
+            (Some(rounding), [RollExpression::Binary(Operator::Divide, lhs, rhs)])
+                if !lhs.divides() && !rhs.divides() =>
+            {
+                let lhs = lhs.distribution_unchecked()?;
+                let rhs = rhs.distribution_unchecked()?;
+                lhs.combine(&rhs, |a, b| rounding.divide(a, b))
+            }
+            (Some(_), [arg]) if !arg.divides() => arg.distribution_unchecked(),
+            (Some(_), _) => Err(Error::Intractable(format!(
+                "{} of anything but a single division is not supported",
+                self
+            ))),
+            (None, [arg]) if *self == Function::Abs => Ok(arg
+                .distribution_unchecked()?
+                .map(|v| v.checked_abs().ok_or(RollError::Overflow))?),
+            (None, [first, rest @ ..]) => {
+                rest.iter()
+                    .try_fold(first.distribution_unchecked()?, |extreme, arg| match self {
+                        Function::Min => {
+                            extreme.combine(&arg.distribution_unchecked()?, |a, b| Ok(a.min(b)))
+                        }
+                        _ => extreme.combine(&arg.distribution_unchecked()?, |a, b| Ok(a.max(b))),
+                    })
+            }
+            (None, []) => Err(RollError::WrongArguments {
+                function: *self,
+                count: 0,
+            }
+            .into()),
+        }
+    }
+}
+
+impl RollExpression {
+    /// Whether rolling the expression divides before any group or function rounds the
+    /// result, so that [`Function::rounding`] would see an exact fraction.
+    fn divides(&self) -> bool {
+        match self {
+            RollExpression::Binary(Operator::Divide, _, _) => true,
+            RollExpression::Binary(_, lhs, rhs) => lhs.divides() || rhs.divides(),
+            RollExpression::Negate(inner) => inner.divides(),
+            _ => false,
+        }
+    }
+
+    /// Computes the exact distribution of outcomes of this expression.
+    ///
+    /// Fails if the expression is invalid, if any outcome is an error (such as dividing
+    /// by a roll of zero), or if the distribution is too expensive to compute exactly.
+    pub fn distribution(&self) -> Result<Distribution, Error> {
+        self.validate()?;
+        self.distribution_unchecked()
+    }
+
+    fn distribution_unchecked(&self) -> Result<Distribution, Error> {
+        match self {
+            RollExpression::Constant(n) => Ok(Distribution::constant(
+                isize::try_from(*n).map_err(|_| RollError::Overflow)?,
+            )),
+            RollExpression::Dice(dice) => dice.distribution(),
+            RollExpression::Nested(nested) => nested.distribution(),
+            RollExpression::If(conditional) => conditional.distribution(),
+            RollExpression::Group(group) => group.distribution(),
+            RollExpression::Variable(name) => Err(RollError::UnboundVariable(name.clone()).into()),
+            RollExpression::Function(function, args) => function.distribution(args),
+            RollExpression::Negate(inner) => Ok(inner
+                .distribution_unchecked()?
+                .map(|v| v.checked_neg().ok_or(RollError::Overflow))?),
+            RollExpression::Binary(op, lhs, rhs) => {
+                let lhs = lhs.distribution_unchecked()?;
+                let rhs = rhs.distribution_unchecked()?;
+                lhs.combine(&rhs, |a, b| op.apply(a, b))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::standard::StandardNotation;
+
+    fn distribution(input: &str) -> Distribution {
+        StandardNotation::parse_from_str(input).unwrap()[0]
+            .distribution()
+            .unwrap()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    pub fn sums_dice() {
+        let d = distribution("2d6");
+        assert_eq!((d.min(), d.max()), (2, 12));
+        assert_close(d.probability(7), 6.0 / 36.0);
+        assert_close(d.mean(), 7.0);
+        assert_close(d.variance(), 35.0 / 6.0);
+        assert_eq!(d.percentile(50.0), 7);
+
+        let d = distribution("d10001");
+        assert_eq!(d.len(), 10001);
+        assert!(matches!(
+            StandardNotation::parse_from_str("d99999999").unwrap()[0].distribution(),
+            Err(Error::Intractable(_))
+        ));
+    }
+
+    #[test]
+    pub fn keeps_highest_and_lowest() {
+        let d = distribution("4d6h3");
+        assert_eq!((d.min(), d.max()), (3, 18));
+        assert_close(d.probability(18), 21.0 / 1296.0);
+        assert_close(d.mean(), 15869.0 / 1296.0);
+
+        let d = distribution("2d20l1");
+        assert_close(d.mean(), 7.175);
+        assert_close(d.probability(20), 1.0 / 400.0);
+
+        assert_eq!(distribution("4d6dl1"), distribution("4d6kh3"));
+        assert_eq!(distribution("3d8dh2"), distribution("3d8kl1"));
+
+        let d = distribution("3d20km1");
+        assert_close(d.mean(), 10.5);
+        assert_close(d.probability(1), 58.0 / 8000.0);
+    }
+
+    #[test]
+    pub fn applies_arithmetic() {
+        let d = distribution("1d8+1d6+4");
+        assert_eq!((d.min(), d.max()), (6, 18));
+        assert_close(d.mean(), 4.5 + 3.5 + 4.0);
+
+        let d = distribution("(1d4+2)*2");
+        assert_eq!(d.iter().map(|(v, _)| v).collect::<Vec<_>>(), [6, 8, 10, 12]);
+
+        let d = distribution("1d4/2");
+        assert_close(d.probability(0), 0.25);
+        assert_close(d.at_least(1), 0.75);
+    }
+
+    #[test]
+    pub fn weighs_nested_dice() {
+        let d = distribution("(1d2)d4");
+        assert_eq!((d.min(), d.max()), (1, 8));
+        assert_close(d.probability(1), 0.5 * 0.25 + 0.5 * 0.0);
+        assert_close(d.probability(8), 0.5 / 16.0);
+        assert_close(d.mean(), 0.5 * 2.5 + 0.5 * 5.0);
+
+        let d = distribution("1d(1d2*2)");
+        assert_close(d.probability(1), 0.5 * 0.5 + 0.5 * 0.25);
+        assert_close(d.probability(4), 0.5 * 0.25);
+
+        let d = distribution("(1d3+1)d6h2");
+        let kept = ["2d6h2", "3d6h2", "4d6h2"].map(distribution);
+        for v in 2..=12 {
+            let p = kept.iter().map(|k| k.probability(v)).sum::<f64>() / 3.0;
+            assert_close(d.probability(v), p);
+        }
+
+        let d = distribution("(1d200)d6");
+        assert_close(d.mean(), 100.5 * 3.5);
+        let rolls = StandardNotation::parse_from_str("(1d10000)d100").unwrap();
+        assert!(matches!(
+            rolls[0].distribution(),
+            Err(Error::Intractable(_))
+        ));
+
+        let d = distribution("(1d2-1)d6");
+        assert_close(d.probability(0), 0.5);
+        assert_close(d.probability(6), 0.5 / 6.0);
+        let d = distribution("(1d3-2)d6kh1");
+        assert_close(d.probability(0), 2.0 / 3.0);
+    }
+
+    #[test]
+    pub fn weighs_conditional_branches() {
+        let d = distribution("if(1d20+5>=15, 2d6, 1d6)");
+        assert_close(d.mean(), 0.55 * 7.0 + 0.45 * 3.5);
+        assert_close(d.probability(12), 0.55 / 36.0);
+
+        // A comparison straight after a die compares its roll, with or without spaces.
+        for input in [
+            "if(1d20>15, 1, 0)",
+            "if(1d20 > 15, 1, 0)",
+            "if(1d20+0>15, 1, 0)",
+        ] {
+            assert_close(distribution(input).probability(1), 0.25);
+        }
+        assert_close(distribution("if(1d6!=6, 1, 0)").probability(1), 5.0 / 6.0);
+        assert_close(distribution("if(1d4 != 1, 1, 0)").probability(1), 0.75);
+        assert_close(distribution("if((1d4>3), 1, 0)").probability(1), 0.5);
+
+        let d = distribution("if(1d2==1d2, 1, 0)");
+        assert_close(d.probability(1), 0.5);
+    }
+
+    #[test]
+    pub fn applies_functions() {
+        let d = distribution("ceil(1d4/2)");
+        assert_eq!(d.iter().map(|(v, _)| v).collect::<Vec<_>>(), [1, 2]);
+        assert_close(d.probability(1), 0.5);
+
+        let d = distribution("round(1d6/4)");
+        assert_close(d.probability(0), 1.0 / 6.0);
+        assert_close(d.probability(2), 1.0 / 6.0);
+
+        let d = distribution("max(1, 1d4-2)");
+        assert_close(d.probability(1), 0.75);
+        assert_close(d.probability(2), 0.25);
+
+        let lowest = distribution("2d6l1");
+        for (v, p) in distribution("min(1d6, 1d6)").iter() {
+            assert_close(p, lowest.probability(v));
+        }
+        assert_eq!(distribution("abs(1d3-2)").max(), 1);
+        assert_eq!(distribution("floor(2d6)"), distribution("2d6"));
+
+        let rolls = StandardNotation::parse_from_str("ceil(1d6/2+1d4/2)").unwrap();
+        assert!(matches!(
+            rolls[0].distribution(),
+            Err(Error::Intractable(_))
+        ));
+    }
+
+    #[test]
+    pub fn sums_fudge_dice() {
+        let d = distribution("4dF");
+        assert_eq!((d.min(), d.max()), (-4, 4));
+        assert_close(d.mean(), 0.0);
+        assert_close(d.probability(4), 1.0 / 81.0);
+    }
+
+    #[test]
+    pub fn weighs_custom_faces() {
+        let d = distribution("d{1,1,2,3,5,8}");
+        assert_close(d.probability(1), 2.0 / 6.0);
+        assert_close(d.mean(), 20.0 / 6.0);
+
+        let d = distribution("2d[0..9]");
+        assert_eq!((d.min(), d.max()), (0, 18));
+        assert_close(d.mean(), 9.0);
+
+        let d = distribution("3d{hit,hit,miss}");
+        assert_eq!(d.iter().collect::<Vec<_>>(), [(0, 1.0)]);
+
+        let d = distribution("1d{-9223372036854775807,9223372036854775807}+0");
+        assert_eq!((d.min(), d.max()), (-isize::MAX, isize::MAX));
+    }
+
+    #[test]
+    pub fn accounts_for_rerolls() {
+        let d = distribution("1d6r1");
+        assert_eq!((d.min(), d.max()), (2, 6));
+        assert_close(d.mean(), 4.0);
+
+        let d = distribution("1d6ro<2");
+        assert_close(d.probability(1), 2.0 / 36.0);
+        assert_close(d.probability(6), 1.0 / 6.0 + 2.0 / 36.0);
+        assert_close(d.mean(), 7.0 / 2.0 * 2.0 / 6.0 + 18.0 / 6.0);
+    }
+
+    #[test]
+    pub fn follows_explosions() {
+        let d = distribution("1d6!");
+        assert_close(d.mean(), 4.2);
+        assert_close(d.probability(6), 0.0);
+        assert_close(d.probability(7), 1.0 / 36.0);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+
+        // Validation refuses these, but a die that always explodes stops at its limit.
+        let dice = Dice {
+            faces: Faces::Standard(1),
+            count: 1,
+            retention: RollRetention::All,
+            modifiers: vec![RollModifier::Explode {
+                compare: ComparePoint::AtLeast(1),
+                kind: Explosion::Standard,
+                limit: Some(3),
+            }],
+        };
+        assert_eq!(dice.distribution().unwrap(), Distribution::constant(4));
+    }
+
+    #[test]
+    pub fn follows_compounding_and_penetrating_explosions() {
+        assert_eq!(distribution("1d6!!"), distribution("1d6!"));
+        assert_eq!(distribution("1d6!>5"), distribution("1d6!5"));
+
+        let d = distribution("1d6!p");
+        assert_close(d.probability(6), 1.0 / 36.0);
+        assert_close(d.probability(7), 1.0 / 36.0);
+        assert_close(d.probability(11), 1.0 / 216.0);
+        assert_close(d.mean(), 3.5 + 2.5 / 5.0);
+
+        let d = distribution("1d6!:1");
+        assert_eq!(d.max(), 12);
+        assert_close(d.probability(12), 1.0 / 36.0);
+
+        let d = distribution("1d6!<1");
+        assert_close(d.probability(1), 0.0);
+
+        let d = distribution("4d6!!kh3");
+        assert!(d.probability(30) > 0.0);
+    }
+
+    #[test]
+    pub fn counts_successes() {
+        let d = distribution("10d10>8");
+        assert_eq!((d.min(), d.max()), (0, 10));
+        assert_close(d.mean(), 3.0);
+
+        let d = distribution("2d10>8f1");
+        assert_close(d.probability(-2), 0.01);
+        assert_close(d.probability(2), 0.09);
+        assert_close(d.mean(), 0.4);
+
+        let d = distribution("1d10>8ds10");
+        assert_close(d.probability(2), 0.1);
+        assert_close(d.mean(), 0.4);
+
+        let d = distribution("1d10>8!");
+        assert_close(d.mean(), 0.3 / 0.9);
+        assert_eq!(distribution("1d10>8!!").max(), 1);
+    }
+
+    #[test]
+    pub fn keeps_group_members() {
+        assert_eq!(distribution("{1d6,1d4}"), distribution("1d6+1d4"));
+
+        let d = distribution("{1d20+5,1d20+5}h1");
+        assert_eq!((d.min(), d.max()), (6, 25));
+        assert_close(d.probability(25), 39.0 / 400.0);
+        assert_close(d.probability(6), 1.0 / 400.0);
+
+        let d = distribution("{1d6,1d4}l1");
+        assert_close(d.probability(4), 3.0 / 24.0);
+        assert_close(d.iter().map(|(_, p)| p).sum(), 1.0);
+
+        let d = distribution("{2d6,1d8,6}kh1");
+        assert_eq!(d.min(), 6);
+        assert!(matches!(
+            StandardNotation::parse_from_str("{1d6,1d6,1d6}h2").unwrap()[0].distribution(),
+            Err(Error::Intractable(_))
+        ));
+    }
+
+    #[test]
+    pub fn renders_histograms() {
+        colored::control::set_override(false);
+        let histogram = distribution("2d6").to_string();
+        assert_eq!(histogram.lines().count(), 11);
+        assert!(histogram.starts_with(" 2   2.78% ########\n"));
+        assert!(distribution("10d10").to_string().lines().count() <= HISTOGRAM_ROWS);
+
+        let faces = (1..=44)
+            .map(|n| n.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let input = format!("d{{{},{},{}}}", isize::MIN + 1, faces, isize::MAX);
+        let histogram = distribution(&input).to_string();
+        assert_eq!(histogram.lines().count(), HISTOGRAM_ROWS);
+        assert!(histogram.starts_with(&format!("{}..", isize::MIN + 1)));
+        assert!(histogram.ends_with(&format!("..{}   2.17% ##\n", isize::MAX)));
+        assert!(!histogram.contains("-0.00%"));
+    }
+
+    #[test]
+    pub fn rejects_uncomputable_expressions() {
+        let rolls = StandardNotation::parse_from_str("6/(1d2-1) 4d6h3! 1d6+@str").unwrap();
+        assert!(matches!(
+            rolls[0].distribution(),
+            Err(Error::Roll(RollError::DivideByZero))
+        ));
+        assert!(matches!(
+            rolls[1].distribution(),
+            Err(Error::Intractable(_))
+        ));
+        assert!(matches!(
+            rolls[2].distribution(),
+            Err(Error::Roll(RollError::UnboundVariable(_)))
+        ));
+
+        let env = Environment::from([("str".to_string(), 3)]);
+        assert_eq!(
+            rolls[2].bind(&env).unwrap().distribution().unwrap(),
+            distribution("1d6+3")
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Helpers for Dungeons & Dragons 5th edition: advantage, disadvantage and critical
+//! hits.
+
+use crate::{
+    Conditional, Dice, Environment, Faces, Group, NestedDice, Operator, RollError, RollExpression,
+    RollQuality, RollResult, RollRetention, TryRoll,
+};
+use rand::Rng;
+use std::fmt::Display;
+
+/// Rebuilds `expression`, replacing any part for which `f` gives a replacement. The
+/// rolled counts and sides of nested dice are left alone. Conditionals have only their
+/// conditions rebuilt if `checks` is set, and only their branches otherwise.
+fn replace(
+    expression: &RollExpression,
+    checks: bool,
+    f: &mut impl FnMut(&RollExpression) -> Option<RollExpression>,
+) -> RollExpression {
+    if let Some(replacement) = f(expression) {
+        return replacement;
+    }
+    let mut replace = |e: &RollExpression| replace(e, checks, f);
+    match expression {
+        RollExpression::Group(group) => RollExpression::Group(Group {
+            members: group.members.iter().map(&mut replace).collect(),
+            retention: group.retention.clone(),
+        }),
+        RollExpression::Negate(inner) => RollExpression::Negate(Box::new(replace(inner))),
+        RollExpression::Binary(op, lhs, rhs) => {
+            RollExpression::Binary(*op, Box::new(replace(lhs)), Box::new(replace(rhs)))
+        }
+        RollExpression::Function(function, args) => {
+            RollExpression::Function(*function, args.iter().map(&mut replace).collect())
+        }
+        RollExpression::If(conditional) if checks => RollExpression::If(Conditional {
+            condition: Box::new(replace(&conditional.condition)),
+            comparison: conditional
+                .comparison
+                .as_ref()
+                .map(|(comparison, rhs)| (*comparison, Box::new(replace(rhs)))),
+            then: conditional.then.clone(),
+            otherwise: conditional.otherwise.clone(),
+        }),
+        RollExpression::If(conditional) => RollExpression::If(Conditional {
+            condition: conditional.condition.clone(),
+            comparison: conditional.comparison.clone(),
+            then: Box::new(replace(&conditional.then)),
+            otherwise: Box::new(replace(&conditional.otherwise)),
+        }),
+        _ => expression.clone(),
+    }
+}
+
+/// Rolls every single d20 checked by `expression` twice, keeping the retained die given
+/// by `retention`. Those in the branches of conditionals, such as damage dealt on a hit,
+/// are left alone. Gives `None` if there is no d20 to roll twice.
+fn roll_twice(expression: &RollExpression, retention: RollRetention) -> Option<RollExpression> {
+    let mut found = false;
+    let expression = replace(expression, true, &mut |e| match e {
+        RollExpression::Dice(dice)
+            if dice.count == 1
+                && dice.faces == Faces::Standard(20)
+                && dice.retention == RollRetention::All =>
+        {
+            found = true;
+            Some(RollExpression::Dice(Dice {
+                count: 2,
+                retention: retention.clone(),
+                ..dice.clone()
+            }))
+        }
+        _ => None,
+    });
+    found.then_some(expression)
+}
+
+/// Rolls with advantage, turning `1d20+5` into `2d20h1+5`. Gives `None` if there is no
+/// single d20 to roll twice.
+pub fn advantage(expression: &RollExpression) -> Option<RollExpression> {
+    roll_twice(expression, RollRetention::Highest(1))
+}
+
+/// Rolls with disadvantage, turning `1d20+5` into `2d20l1+5`. Gives `None` if there is
+/// no single d20 to roll twice.
+pub fn disadvantage(expression: &RollExpression) -> Option<RollExpression> {
+    roll_twice(expression, RollRetention::Lowest(1))
+}
+
+/// Keeps or drops twice as many dice, for twice as many rolled.
+fn doubled(retention: &RollRetention) -> RollRetention {
+    match *retention {
+        RollRetention::Highest(n) => RollRetention::Highest(n.saturating_mul(2)),
+        RollRetention::Lowest(n) => RollRetention::Lowest(n.saturating_mul(2)),
+        RollRetention::Middle(n) => RollRetention::Middle(n.saturating_mul(2)),
+        RollRetention::DropHighest(n) => RollRetention::DropHighest(n.saturating_mul(2)),
+        RollRetention::DropLowest(n) => RollRetention::DropLowest(n.saturating_mul(2)),
+        RollRetention::All => RollRetention::All,
+    }
+}
+
+/// Doubles the number of dice rolled for critical damage, turning `2d6+1d8+3` into
+/// `4d6+2d8+3` and `4d6h3` into `8d6h6`. Modifiers are not doubled.
+pub fn critical(expression: &RollExpression) -> RollExpression {
+    replace(expression, false, &mut |e| match e {
+        RollExpression::Dice(dice) => Some(RollExpression::Dice(Dice {
+            count: dice.count.saturating_mul(2),
+            retention: doubled(&dice.retention),
+            ..dice.clone()
+        })),
+        RollExpression::Nested(nested) => Some(RollExpression::Nested(NestedDice {
+            count: nested.count.as_ref().map(|count| {
+                Box::new(RollExpression::Binary(
+                    Operator::Multiply,
+                    Box::new(RollExpression::Constant(2)),
+                    count.clone(),
+                ))
+            }),
+            sides: nested.sides.clone(),
+            dice: Dice {
+                count: nested.dice.count.saturating_mul(2),
+                retention: doubled(&nested.dice.retention),
+                ..nested.dice.clone()
+            },
+        })),
+        _ => None,
+    })
+}
+
+/// Whether the attack rolled a natural 20, or whatever its d20 counts as a critical
+/// success, on a die it kept.
+pub fn natural_20(attack: &RollResult) -> bool {
+    attack
+        .terms
+        .iter()
+        .filter(|t| t.faces == Faces::Standard(20))
+        .flat_map(|t| t.rolls.iter())
+        .any(|r| r.retained && *r.criticality() == RollQuality::Good)
+}
+
+/// An attack roll together with the damage it deals on a hit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Attack {
+    pub attack: RollExpression,
+    pub damage: RollExpression,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AttackResult {
+    pub attack: RollResult,
+    /// The damage rolled, with its dice doubled on a critical hit.
+    pub damage: RollResult,
+    pub critical: bool,
+}
+
+impl Display for AttackResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.attack)?;
+        write!(f, "{}", self.damage)?;
+        if self.critical {
+            write!(f, " (critical)")?;
+        }
+        Ok(())
+    }
+}
+
+impl Attack {
+    pub fn try_roll(&self) -> Result<AttackResult, RollError> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    pub fn try_roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<AttackResult, RollError> {
+        self.try_roll_in(&Environment::new(), rng)
+    }
+
+    /// Rolls the attack, then its damage, doubling the damage dice if the attack rolled
+    /// a natural 20.
+    pub fn try_roll_in<R: Rng + ?Sized>(
+        &self,
+        env: &Environment,
+        rng: &mut R,
+    ) -> Result<AttackResult, RollError> {
+        let attack = self.attack.try_roll_in(env, rng)?;
+        let hit = natural_20(&attack);
+        let damage = match hit {
+            true => critical(&self.damage).try_roll_in(env, rng)?,
+            false => self.damage.try_roll_in(env, rng)?,
+        };
+        Ok(AttackResult {
+            attack,
+            damage,
+            critical: hit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn parse(input: &str) -> RollExpression {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    pub fn rolls_d20s_twice() {
+        let roll = parse("1d20+5");
+        assert_eq!(advantage(&roll).unwrap().to_string(), "2d20h1+5");
+        assert_eq!(disadvantage(&roll).unwrap().to_string(), "2d20l1+5");
+
+        let roll = parse("1d20cs19+1d4+2");
+        assert_eq!(advantage(&roll).unwrap().to_string(), "2d20h1cs19+1d4+2");
+
+        let roll = parse("if(1d20+5>=15, 2d6, 1d6)");
+        assert_eq!(
+            advantage(&roll).unwrap().to_string(),
+            "if(2d20h1+5>=15,2d6,1d6)"
+        );
+        let roll = parse("if(15<=1d20, 1d20, 0)");
+        assert_eq!(
+            disadvantage(&roll).unwrap().to_string(),
+            "if(15<=2d20l1,1d20,0)"
+        );
+        assert_eq!(advantage(&parse("if(1d6>3, 1d20, 0)")), None);
+        assert_eq!(
+            parse("if(1d20+5>=15, 2d6, 1d6) adv").to_string(),
+            "if(2d20h1+5>=15,2d6,1d6)"
+        );
+
+        assert_eq!(advantage(&parse("2d6+3")), None);
+        assert_eq!(advantage(&parse("2d20h1")), None);
+    }
+
+    #[test]
+    pub fn doubles_dice_but_not_modifiers() {
+        for (input, doubled) in [
+            ("2d6+1d8+3", "4d6+2d8+3"),
+            ("1d12!+@str", "2d12!+@str"),
+            ("(1d4)d6", "(2x1d4)d6"),
+            ("max(1, 1d4-2)", "max(1,2d4-2)"),
+            ("if(1d20>=15, 2d6, 1d6)", "if(1d20>=15,4d6,2d6)"),
+            ("4d6h3+2d20l1", "8d6h6+4d20l2"),
+            ("(1d4)d6dl1", "(2x1d4)d6dl2"),
+        ] {
+            assert_eq!(critical(&parse(input)).to_string(), doubled);
+        }
+    }
+
+    #[test]
+    pub fn applies_critical_damage_on_a_natural_20() {
+        let attack = Attack {
+            attack: parse("1d20+5"),
+            damage: parse("1d8+3"),
+        };
+        let mut rng = StdRng::seed_from_u64(23);
+        let mut critical = false;
+        for _ in 0..200 {
+            let result = attack.try_roll_with(&mut rng).unwrap();
+            let natural = result.attack.terms[0].rolls[0].value == 20;
+            assert_eq!(result.critical, natural);
+            let dice = if natural { "2d8+3" } else { "1d8+3" };
+            assert_eq!(result.damage.input, dice);
+            critical |= natural;
+        }
+        assert!(critical);
+
+        let bless = Attack {
+            attack: parse("1d20+1d4cs>1"),
+            damage: parse("1d6"),
+        };
+        let result = bless.try_roll_with(&mut rng).unwrap();
+        assert_eq!(
+            result.critical,
+            result.attack.terms[0].rolls[0].value == 20,
+            "only the d20 decides a critical hit"
+        );
+
+        let champion = Attack {
+            attack: parse("1d20cs>19"),
+            damage: parse("1d6"),
+        };
+        let result = champion.try_roll_with(&mut rng).unwrap();
+        assert_eq!(result.critical, result.attack.terms[0].rolls[0].value >= 19);
+
+        let target = Attack {
+            attack: parse("1d20>10"),
+            damage: parse("1d6"),
+        };
+        for _ in 0..100 {
+            let result = target.try_roll_with(&mut rng).unwrap();
+            assert_eq!(
+                result.critical,
+                result.attack.terms[0].rolls[0].value == 20,
+                "a success is not a critical hit"
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{ComparePoint, Faces, Function};
+use std::{fmt::Display, ops::Range};
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Roll(RollError),
+    /// The exact distribution of an expression is too expensive to compute.
+    Intractable(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Roll(e) => write!(f, "{}", e),
+            Error::Intractable(reason) => {
+                write!(f, "cannot compute exact distribution: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ParseError> for Error {
+    fn from(value: ParseError) -> Self {
+        Error::Parse(value)
+    }
+}
+
+impl From<RollError> for Error {
+    fn from(value: RollError) -> Self {
+        Error::Roll(value)
+    }
+}
+
+/// An expression that parsed, but cannot be rolled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollError {
+    NoDice,
+    NoFaces,
+    TooManyDice {
+        count: usize,
+        max: usize,
+    },
+    /// The explosion matches none or all of the faces of the die.
+    ExplodeOutOfRange {
+        compare: ComparePoint,
+        faces: Faces,
+    },
+    RetainTooMany {
+        retain: usize,
+        count: usize,
+    },
+    DropTooMany {
+        drop: usize,
+        count: usize,
+    },
+    /// Every face of the die would be rerolled, so rolling would never finish.
+    RerollsEveryFace {
+        faces: Faces,
+    },
+    /// A function was called with the wrong number of arguments.
+    WrongArguments {
+        function: Function,
+        count: usize,
+    },
+    /// A variable had no value in the environment it was rolled in.
+    UnboundVariable(String),
+    TooManyRepeats {
+        count: usize,
+        max: usize,
+    },
+    /// A simulation was asked for no rolls at all.
+    NoSamples,
+    /// A World of Darkness chance die was rolled again or as a rote, which it never is.
+    ChanceDie,
+    /// Failures or double successes were counted without a success target.
+    NoSuccessTarget,
+    DivideByZero,
+    Overflow,
+}
+
+impl Display for RollError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RollError::NoDice => write!(f, "must roll at least one die"),
+            RollError::NoFaces => write!(f, "dice must have at least one face"),
+            RollError::TooManyDice { count, max } => {
+                write!(f, "cannot roll {} dice at once (maximum {})", count, max)
+            }
+            RollError::ExplodeOutOfRange { faces, .. } if faces.min() + 1 > faces.max() => {
+                write!(f, "cannot explode a d{}", faces)
+            }
+            RollError::ExplodeOutOfRange { compare, faces } => write!(
+                f,
+                "cannot explode on {} with a d{}, it must match some but not all faces",
+                compare, faces
+            ),
+            RollError::RetainTooMany { retain, count } => {
+                write!(f, "cannot keep {} of {} dice", retain, count)
+            }
+            RollError::DropTooMany { drop, count } => {
+                write!(f, "cannot drop {} of {} dice", drop, count)
+            }
+            RollError::RerollsEveryFace { faces } => {
+                write!(f, "cannot reroll every face of a d{}", faces)
+            }
+            RollError::WrongArguments {
+                function: function @ (Function::Min | Function::Max),
+                ..
+            } => write!(f, "{} takes at least one argument", function),
+            RollError::WrongArguments { function, count } => {
+                write!(f, "{} takes one argument, not {}", function, count)
+            }
+            RollError::UnboundVariable(name) => write!(f, "no value for @{}", name),
+            RollError::TooManyRepeats { count, max } => {
+                write!(f, "cannot repeat a roll {} times (maximum {})", count, max)
+            }
+            RollError::NoSamples => write!(f, "must simulate at least one roll"),
+            RollError::ChanceDie => {
+                write!(
+                    f,
+                    "a chance die is never rolled again nor rerolled as a rote"
+                )
+            }
+            RollError::NoSuccessTarget => {
+                write!(
+                    f,
+                    "cannot count failures or double successes without a target"
+                )
+            }
+            RollError::DivideByZero => write!(f, "division by zero"),
+            RollError::Overflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+impl std::error::Error for RollError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// The full input that failed to parse.
+    pub input: String,
+    /// Byte range of the offending input. Empty when the error points between characters.
+    pub span: Range<usize>,
+    /// Human-readable names of the tokens that would have been accepted at `span`.
+    pub expected: Vec<String>,
+    pub message: String,
+}
+
+impl ParseError {
+    /// Renders the offending line of input with a caret under the error, e.g.
+    ///
+    /// ```text
+    /// 3d10 3+
+    ///        ^ expected number or `-`
+    /// ```
+    pub fn render(&self) -> String {
+        let start = self.span.start.min(self.input.len());
+        let end = self.span.end.clamp(start, self.input.len());
+
+        let line_start = self.input[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.input[start..]
+            .find('\n')
+            .map_or(self.input.len(), |i| start + i);
+        let line = &self.input[line_start..line_end];
+
+        let column = self.input[line_start..start].chars().count();
+        let width = self.input[start..end.min(line_end)].chars().count().max(1);
+
+        format!(
+            "{}\n{}{} {}",
+            line,
+            " ".repeat(column),
+            "^".repeat(width),
+            self.message
+        )
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.span.start)
+    }
+}
+
+impl std::error::Error for ParseError {}
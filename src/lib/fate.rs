@@ -0,0 +1,46 @@
+//! Helpers for the Fate roleplaying game, which rolls Fudge dice (`4dF`).
+
+const LADDER: &[(isize, &str)] = &[
+    (8, "Legendary"),
+    (7, "Epic"),
+    (6, "Fantastic"),
+    (5, "Superb"),
+    (4, "Great"),
+    (3, "Good"),
+    (2, "Fair"),
+    (1, "Average"),
+    (0, "Mediocre"),
+    (-1, "Poor"),
+    (-2, "Terrible"),
+    (-3, "Catastrophic"),
+    (-4, "Horrifying"),
+];
+
+/// The name of a total on the Fate ladder, if it has one.
+pub fn ladder(total: isize) -> Option<&'static str> {
+    LADDER
+        .iter()
+        .find_map(|(n, name)| if *n == total { Some(*name) } else { None })
+}
+
+/// Describes a total on the Fate ladder, such as "Good (+3)". Totals off the ladder are
+/// shown as just the signed number.
+pub fn describe(total: isize) -> String {
+    match ladder(total) {
+        Some(name) => format!("{} ({:+})", name, total),
+        None => format!("{:+}", total),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn describes_the_ladder() {
+        assert_eq!(describe(3), "Good (+3)");
+        assert_eq!(describe(0), "Mediocre (+0)");
+        assert_eq!(describe(-2), "Terrible (-2)");
+        assert_eq!(describe(9), "+9");
+    }
+}
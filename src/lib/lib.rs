@@ -1,229 +1,1794 @@
 use rand::Rng;
-use std::{fmt::Debug, fmt::Display, io::Error};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    fmt::Display,
+};
 
+mod distribution;
+pub mod dnd;
+mod error;
+pub mod fate;
+pub mod shadowrun;
+mod simulation;
 pub mod standard;
+pub mod wod;
 
-#[derive(Debug)]
+pub use distribution::Distribution;
+pub use error::{Error, ParseError, RollError};
+pub use simulation::{simulate, Simulation, Z_95};
+
+/// The most dice a single term may roll, before any explosions.
+pub const MAX_DICE: usize = 10_000;
+
+/// The most times a single roll may be repeated, as in `6#4d6h3`.
+pub const MAX_REPEATS: usize = 1_000;
+
+/// How deeply brackets may nest in a single input, as in `((1d6)d6)d6`.
+pub const MAX_NESTING: usize = 64;
+
+/// How many times a single die may explode when its modifier sets no limit.
+pub const MAX_EXPLOSIONS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RollRetention {
     Highest(usize),
     Lowest(usize),
+    /// Keeps the middle n dice. When the rest cannot be split evenly, one more die is
+    /// dropped from the top than from the bottom.
+    Middle(usize),
+    DropHighest(usize),
+    DropLowest(usize),
     All,
 }
 
-#[derive(Debug, Clone)]
+impl Display for RollRetention {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RollRetention::All => Ok(()),
+            RollRetention::Highest(n) => write!(f, "h{}", n),
+            RollRetention::Lowest(n) => write!(f, "l{}", n),
+            RollRetention::Middle(n) => write!(f, "km{}", n),
+            RollRetention::DropHighest(n) => write!(f, "dh{}", n),
+            RollRetention::DropLowest(n) => write!(f, "dl{}", n),
+        }
+    }
+}
+
+impl RollRetention {
+    /// Checks that the retention can be applied to `count` dice or group members.
+    fn validate(&self, count: usize) -> Result<(), RollError> {
+        match *self {
+            RollRetention::Highest(n) | RollRetention::Lowest(n) | RollRetention::Middle(n)
+                if n > count =>
+            {
+                Err(RollError::RetainTooMany { retain: n, count })
+            }
+            // Dropping everything would always total zero.
+            RollRetention::DropHighest(n) | RollRetention::DropLowest(n) if n >= count => {
+                Err(RollError::DropTooMany { drop: n, count })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Which of `values` are kept, dropping the lowest and highest as required.
+    fn retained(&self, values: &[isize]) -> Vec<bool> {
+        let mut order = (0..values.len()).collect::<Vec<usize>>();
+        order.sort_by_key(|i| values[*i]);
+        let (low, high) = self.dropped(values.len());
+
+        let mut retained = vec![true; values.len()];
+        for i in order[..low]
+            .iter()
+            .chain(order[order.len() - high..].iter())
+        {
+            retained[*i] = false;
+        }
+        retained
+    }
+
+    /// How many of `len` rolled dice are dropped from the bottom and from the top.
+    fn dropped(&self, len: usize) -> (usize, usize) {
+        match *self {
+            RollRetention::Highest(n) => (len.saturating_sub(n), 0),
+            RollRetention::Lowest(n) => (0, len.saturating_sub(n)),
+            RollRetention::Middle(n) => {
+                let dropped = len.saturating_sub(n);
+                (dropped / 2, dropped - dropped / 2)
+            }
+            RollRetention::DropHighest(n) => (0, n.min(len)),
+            RollRetention::DropLowest(n) => (n.min(len), 0),
+            RollRetention::All => (0, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RollQuality {
     Good,
     Regular,
     Bad,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RollItem {
-    pub value: usize,
+    pub value: isize,
     pub retained: bool,
+    /// Whether the die is a critical success or failure. Dice counting successes without
+    /// `cs` or `cf` modifiers show whether they succeeded or failed instead.
     pub quality: RollQuality,
+    /// The successes the die counts for, when its term has a success target.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub successes: Option<isize>,
+    /// Whether the die is a critical success or failure, when `quality` shows whether it
+    /// succeeded instead.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub critical: Option<RollQuality>,
+    /// The face that was rolled, for dice with symbolic faces.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub symbol: Option<String>,
+    /// Why the die was not retained, if it was not.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub reason: Option<DiscardReason>,
 }
 
-#[derive(Debug)]
-pub struct RollResult {
+impl RollItem {
+    /// Whether the die is a critical success or failure, even when its `quality` shows
+    /// whether it succeeded.
+    pub fn criticality(&self) -> &RollQuality {
+        self.critical.as_ref().unwrap_or(&self.quality)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DiscardReason {
+    /// Dropped by the term's [`RollRetention`].
+    Dropped,
+    /// Replaced by a reroll.
+    Rerolled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TermResult {
     pub input: String,
     pub total: isize,
+    pub faces: Faces,
     pub rolls: Vec<RollItem>,
+    /// The net number of successes, for dice with a success target. This is also the
+    /// term's total.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub successes: Option<isize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RollResult {
+    pub input: String,
+    pub total: isize,
+    pub terms: Vec<TermResult>,
+    /// Every roll group in the expression, in the order they were rolled. The dice of
+    /// members a group did not keep are marked as not retained in `terms`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub groups: Vec<GroupResult>,
+    /// Every die expression whose count or sides were rolled, in the order they were
+    /// rolled. The rolls that decided them come just before their dice in `terms`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub nested: Vec<NestedResult>,
+    /// Every conditional in the expression that was rolled, in the order they were
+    /// rolled. The rolls of the condition come just before those of the chosen branch
+    /// in `terms`.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    pub conditions: Vec<ConditionResult>,
+}
+
+/// How a [`Conditional`] was decided.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConditionResult {
+    pub input: String,
+    /// The total of the left side of the condition, or of the whole condition when it
+    /// compares nothing.
+    pub total: isize,
+    /// The comparison made and the total of its right side, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub compared: Option<(Comparison, isize)>,
+    pub passed: bool,
+    /// The branch that was rolled.
+    pub branch: String,
+}
+
+/// The dice actually rolled for a [`NestedDice`], such as `3d6` for `(1d4)d6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NestedResult {
+    pub input: String,
+    pub count: usize,
+    pub faces: Faces,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupResult {
+    pub input: String,
+    pub total: isize,
+    pub members: Vec<GroupMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupMember {
+    pub input: String,
+    pub total: isize,
+    pub retained: bool,
+}
+
+impl RollResult {
+    pub fn rolls(&self) -> impl Iterator<Item = &RollItem> {
+        self.terms.iter().flat_map(|t| t.rolls.iter())
+    }
+
+    /// Counts how many of each symbolic face were rolled and retained, such as the
+    /// hits and misses of `4d{hit,hit,miss,crit}`.
+    pub fn tally(&self) -> BTreeMap<&str, usize> {
+        let mut tally = BTreeMap::new();
+        for symbol in self
+            .rolls()
+            .filter(|r| r.retained)
+            .filter_map(|r| r.symbol.as_deref())
+        {
+            *tally.entry(symbol).or_insert(0) += 1;
+        }
+        tally
+    }
+
+    /// Whether any retained die is a critical success, such as a natural 20 on a d20.
+    pub fn critical(&self) -> bool {
+        self.rolls()
+            .any(|r| r.retained && *r.criticality() == RollQuality::Good)
+    }
+
+    /// Whether any retained die is a critical failure, such as a natural 1 on a d20.
+    pub fn fumble(&self) -> bool {
+        self.rolls()
+            .any(|r| r.retained && *r.criticality() == RollQuality::Bad)
+    }
+
+    /// The net number of successes of every term with a success target, if any have one.
+    pub fn successes(&self) -> Option<isize> {
+        self.terms
+            .iter()
+            .filter_map(|t| t.successes)
+            .reduce(|a, b| a.saturating_add(b))
+    }
 }
 
 impl Display for RollResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use colored::{ColoredString, Colorize};
 
-        write!(f, "{:<10}: {:<4} [", self.input, self.total)?;
+        write!(f, "{:<10}: {:<4}", self.input, self.total)?;
 
-        for (i, r) in self.rolls.iter().enumerate() {
-            let mut k: ColoredString = r.value.to_string().normal();
-            if !r.retained {
-                k = k.strikethrough();
-            }
-            match r.quality {
-                RollQuality::Good => {
-                    k = k.green();
+        for term in self.terms.iter() {
+            write!(f, " [")?;
+
+            for (i, r) in term.rolls.iter().enumerate() {
+                let mut k: ColoredString = match &r.symbol {
+                    Some(symbol) => symbol.normal(),
+                    None => term.faces.label(r.value).normal(),
+                };
+                if !r.retained {
+                    k = k.strikethrough();
                 }
-                RollQuality::Bad => {
-                    k = k.red();
+                // Successes and failures show before critical faces.
+                match (r.successes, &r.quality) {
+                    (Some(n), _) if n > 0 => k = k.green(),
+                    (Some(n), _) if n < 0 => k = k.red(),
+                    (_, RollQuality::Good) => k = k.green(),
+                    (_, RollQuality::Bad) => k = k.red(),
+                    _ => {}
+                }
+                write!(f, "{}", k)?;
+
+                if i != term.rolls.len() - 1 {
+                    write!(f, ", ")?;
                 }
-                _ => {}
             }
-            write!(f, "{}", k)?;
 
-            if i != self.rolls.len() - 1 {
-                write!(f, ", ")?;
+            write!(f, "]")?;
+
+            match term.successes {
+                Some(1) => write!(f, " (1 success)")?,
+                Some(n) => write!(f, " ({} successes)", n)?,
+                None => {}
+            }
+        }
+
+        for group in self.groups.iter() {
+            let members = group
+                .members
+                .iter()
+                .map(|m| {
+                    let total = m.total.to_string().normal();
+                    if m.retained {
+                        total.to_string()
+                    } else {
+                        total.strikethrough().to_string()
+                    }
+                })
+                .collect::<Vec<String>>();
+            write!(f, " {{{}}}", members.join(", "))?;
+        }
+
+        for nested in self.nested.iter() {
+            write!(f, " (rolled {}d{})", nested.count, nested.faces)?;
+        }
+
+        for condition in self.conditions.iter() {
+            let not = if condition.passed { "" } else { "not " };
+            match condition.compared {
+                Some((comparison, rhs)) => write!(
+                    f,
+                    " (if {}{}{}{}: {})",
+                    not, condition.total, comparison, rhs, condition.branch
+                )?,
+                None => write!(f, " (if {}{}: {})", not, condition.total, condition.branch)?,
             }
         }
 
-        write!(f, "]")
+        let tally = self.tally();
+        if !tally.is_empty() {
+            let counts = tally
+                .iter()
+                .map(|(symbol, n)| format!("{} {}", n, symbol))
+                .collect::<Vec<String>>();
+            write!(f, " ({})", counts.join(", "))?;
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RollModifier {
-    Add(usize),
-    Subtract(usize),
-    Multiply(usize),
-    Divide(usize),
-    Explode(usize),
+    /// Rolls another die whenever a die matches the comparison point, up to `limit`
+    /// times per die, or [`MAX_EXPLOSIONS`] if unset.
+    Explode {
+        compare: ComparePoint,
+        kind: Explosion,
+        limit: Option<usize>,
+    },
+    /// Rerolls dice matching the comparison point, either once or until they no longer
+    /// match.
+    Reroll { compare: ComparePoint, once: bool },
+    /// Counts dice matching the comparison point as one success each, making the
+    /// term's total the number of successes rather than the sum of the dice.
+    Success(ComparePoint),
+    /// Subtracts one success for each die matching the comparison point.
+    Failure(ComparePoint),
+    /// Counts dice matching the comparison point as two successes.
+    DoubleSuccess(ComparePoint),
+    /// Marks dice matching the comparison point as critical successes, instead of
+    /// only the highest face.
+    CriticalSuccess(ComparePoint),
+    /// Marks dice matching the comparison point as critical failures, instead of only
+    /// the lowest face.
+    CriticalFailure(ComparePoint),
 }
 
-#[derive(Debug)]
-pub struct RollExpression {
-    pub faces: usize,
-    pub count: usize,
-    pub retention: RollRetention,
-    pub modifiers: Vec<RollModifier>,
+/// How the extra dice of an explosion are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Explosion {
+    /// Each extra roll is a separate die, as in `!`.
+    Standard,
+    /// Extra rolls are added into the die that exploded, as in `!!`.
+    Compound,
+    /// Each extra roll is a separate die with one subtracted, as in `!p`. Whether it
+    /// explodes again depends on the face before subtracting.
+    Penetrate,
 }
 
-impl RollExpression {
-    fn explodes_at(&self) -> Option<usize> {
-        self.modifiers.iter().find_map(|m| match m {
-            RollModifier::Explode(n) => {
-                if *n <= self.faces && *n >= 1 {
-                    Some(*n)
-                } else {
-                    panic!("Cannot explode above {}", n)
-                }
+/// The faces a modifier applies to. As in Roll20, `<` and `>` include the value itself,
+/// the same as `<=` and `>=`, so `r<2` rerolls both 1s and 2s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ComparePoint {
+    Equal(isize),
+    AtMost(isize),
+    AtLeast(isize),
+}
+
+impl Display for ComparePoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ComparePoint::Equal(n) => write!(f, "={}", n),
+            ComparePoint::AtMost(n) => write!(f, "<{}", n),
+            ComparePoint::AtLeast(n) => write!(f, ">{}", n),
+        }
+    }
+}
+
+impl ComparePoint {
+    pub fn matches(&self, value: isize) -> bool {
+        match *self {
+            ComparePoint::Equal(n) => value == n,
+            ComparePoint::AtMost(n) => value <= n,
+            ComparePoint::AtLeast(n) => value >= n,
+        }
+    }
+
+    /// The values around the edge of the matched faces, where runs of faces that do
+    /// and do not match begin and end.
+    fn edges(&self) -> [isize; 3] {
+        match *self {
+            ComparePoint::Equal(n) | ComparePoint::AtMost(n) | ComparePoint::AtLeast(n) => {
+                [n.saturating_sub(1), n, n.saturating_add(1)]
             }
-            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operator {
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 1,
+            Operator::Multiply | Operator::Divide => 2,
+        }
+    }
+
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "x",
+            Operator::Divide => "/",
+        }
+    }
+
+    fn apply(&self, lhs: isize, rhs: isize) -> Result<isize, RollError> {
+        match self {
+            Operator::Add => lhs.checked_add(rhs),
+            Operator::Subtract => lhs.checked_sub(rhs),
+            Operator::Multiply => lhs.checked_mul(rhs),
+            Operator::Divide => return Rounding::Down.divide(lhs, rhs),
+        }
+        .ok_or(RollError::Overflow)
+    }
+}
+
+/// How a division that leaves a remainder is rounded. Plain division with `/` always
+/// rounds down, and [`Function::Ceil`] or [`Function::Round`] choose otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Rounding {
+    /// Toward negative infinity, so `7/2` is 3 and `-7/2` is -4.
+    Down,
+    /// Toward positive infinity, so `7/2` is 4 and `-7/2` is -3.
+    Up,
+    /// To the nearest whole number, with halves rounded away from zero.
+    Nearest,
+}
+
+impl Rounding {
+    pub fn divide(&self, lhs: isize, rhs: isize) -> Result<isize, RollError> {
+        if rhs == 0 {
+            return Err(RollError::DivideByZero);
+        }
+        let quotient = lhs.checked_div(rhs).ok_or(RollError::Overflow)?;
+        let remainder = lhs % rhs;
+        if remainder == 0 {
+            return Ok(quotient);
+        }
+
+        // The quotient was truncated toward zero, so step away from it as needed.
+        let away = if (remainder < 0) != (rhs < 0) { -1 } else { 1 };
+        let step = match self {
+            Rounding::Down => away.min(0),
+            Rounding::Up => away.max(0),
+            Rounding::Nearest
+                if remainder.unsigned_abs() >= rhs.unsigned_abs() - remainder.unsigned_abs() =>
+            {
+                away
+            }
+            Rounding::Nearest => 0,
+        };
+        quotient.checked_add(step).ok_or(RollError::Overflow)
+    }
+}
+
+/// An exact quotient, so that rounding functions see the true value of their argument
+/// rather than one already rounded down by `/`.
+#[derive(Debug, Clone, Copy)]
+struct Fraction {
+    numerator: isize,
+    /// Always positive, and shares no factor with the numerator.
+    denominator: isize,
+}
+
+impl From<isize> for Fraction {
+    fn from(value: isize) -> Self {
+        Fraction {
+            numerator: value,
+            denominator: 1,
+        }
+    }
+}
+
+impl Fraction {
+    fn new(numerator: isize, denominator: isize) -> Result<Fraction, RollError> {
+        if denominator == 0 {
+            return Err(RollError::DivideByZero);
+        }
+        let (mut a, mut b) = (numerator.unsigned_abs(), denominator.unsigned_abs());
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        let divisor = isize::try_from(a).map_err(|_| RollError::Overflow)?;
+        let sign = denominator.signum();
+        Ok(Fraction {
+            numerator: (numerator / divisor)
+                .checked_mul(sign)
+                .ok_or(RollError::Overflow)?,
+            denominator: (denominator / divisor)
+                .checked_mul(sign)
+                .ok_or(RollError::Overflow)?,
         })
     }
+
+    fn apply(&self, op: Operator, rhs: Fraction) -> Result<Fraction, RollError> {
+        let (a, b) = (self.numerator, self.denominator);
+        let (c, d) = (rhs.numerator, rhs.denominator);
+        let cross = || Some((a.checked_mul(d)?, c.checked_mul(b)?, b.checked_mul(d)?));
+        let (numerator, denominator) = match op {
+            Operator::Add => cross().and_then(|(ad, cb, bd)| Some((ad.checked_add(cb)?, bd))),
+            Operator::Subtract => cross().and_then(|(ad, cb, bd)| Some((ad.checked_sub(cb)?, bd))),
+            Operator::Multiply => a.checked_mul(c).zip(b.checked_mul(d)),
+            Operator::Divide if c == 0 => return Err(RollError::DivideByZero),
+            Operator::Divide => a.checked_mul(d).zip(b.checked_mul(c)),
+        }
+        .ok_or(RollError::Overflow)?;
+        Fraction::new(numerator, denominator)
+    }
+
+    fn round(&self, rounding: Rounding) -> Result<isize, RollError> {
+        rounding.divide(self.numerator, self.denominator)
+    }
 }
 
-pub trait Roll {
-    fn roll(&mut self) -> RollResult;
+/// A built-in function, such as `ceil(3d6/2)` or `max(1, 1d4-2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Function {
+    Floor,
+    Ceil,
+    Round,
+    Abs,
+    Min,
+    Max,
 }
 
-impl Roll for RollExpression {
-    fn roll(&mut self) -> RollResult {
-        let mut rng = rand::thread_rng();
-        let mut rolls: Vec<RollItem> = Vec::with_capacity(self.count);
+impl Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Round => "round",
+            Function::Abs => "abs",
+            Function::Min => "min",
+            Function::Max => "max",
+        };
+        write!(f, "{}", name)
+    }
+}
 
-        let explode_at = self.explodes_at();
+impl Function {
+    /// How the function rounds the exact value of its argument, if it is one of the
+    /// rounding functions.
+    pub fn rounding(&self) -> Option<Rounding> {
+        match self {
+            Function::Floor => Some(Rounding::Down),
+            Function::Ceil => Some(Rounding::Up),
+            Function::Round => Some(Rounding::Nearest),
+            Function::Abs | Function::Min | Function::Max => None,
+        }
+    }
 
-        for _ in 0..self.count {
-            let mut value = rng.gen_range(1..=self.faces);
-            rolls.push(RollItem {
-                value,
-                retained: true,
-                quality: match value {
-                    v if v == self.faces => RollQuality::Good,
-                    1 => RollQuality::Bad,
-                    _ => RollQuality::Regular,
-                },
+    fn validate(&self, args: &[RollExpression]) -> Result<(), RollError> {
+        let allowed = match self {
+            Function::Min | Function::Max => !args.is_empty(),
+            _ => args.len() == 1,
+        };
+        if !allowed {
+            return Err(RollError::WrongArguments {
+                function: *self,
+                count: args.len(),
             });
+        }
+        for arg in args.iter() {
+            arg.validate()?;
+        }
+        Ok(())
+    }
 
-            if let Some(n) = explode_at {
-                while value >= n {
-                    value = rng.gen_range(1..=self.faces);
-                    rolls.push(RollItem {
-                        value,
-                        retained: true,
-                        quality: match value {
-                            v if v == self.faces => RollQuality::Good,
-                            1 => RollQuality::Bad,
-                            _ => RollQuality::Regular,
-                        },
-                    });
-                }
+    fn evaluate<R: Rng + ?Sized>(
+        &self,
+        args: &[RollExpression],
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<isize, RollError> {
+        if let (Some(rounding), [arg]) = (self.rounding(), args) {
+            return arg.evaluate_exact(rng, env, result)?.round(rounding);
+        }
+
+        let values = args
+            .iter()
+            .map(|arg| arg.evaluate(rng, env, result))
+            .collect::<Result<Vec<isize>, RollError>>()?;
+        match (self, values.as_slice()) {
+            (Function::Abs, [value]) => value.checked_abs().ok_or(RollError::Overflow),
+            (Function::Min, [_, ..]) => Ok(values.into_iter().min().unwrap_or_default()),
+            (Function::Max, [_, ..]) => Ok(values.into_iter().max().unwrap_or_default()),
+            _ => Err(RollError::WrongArguments {
+                function: *self,
+                count: args.len(),
+            }),
+        }
+    }
+}
+
+/// A single face of a die with custom faces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Face {
+    Number(isize),
+    /// A face marked with a symbol rather than a number, which counts as zero.
+    Symbol(String),
+}
+
+impl Display for Face {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Face::Number(n) => write!(f, "{}", n),
+            Face::Symbol(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Face {
+    pub fn value(&self) -> isize {
+        match self {
+            Face::Number(n) => *n,
+            Face::Symbol(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Faces {
+    /// A die numbered from 1 to n.
+    Standard(usize),
+    /// A Fudge or Fate die, with faces -1, 0 and +1.
+    Fudge,
+    /// A die numbered with every integer from the first to the last, inclusive.
+    Range(isize, isize),
+    /// A die with the listed faces, each equally likely. Faces may repeat.
+    Custom(Vec<Face>),
+}
+
+impl Display for Faces {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Faces::Standard(n) => write!(f, "{}", n),
+            Faces::Fudge => write!(f, "F"),
+            Faces::Range(start, end) => write!(f, "[{}..{}]", start, end),
+            Faces::Custom(faces) => {
+                let faces = faces.iter().map(|f| f.to_string()).collect::<Vec<String>>();
+                write!(f, "{{{}}}", faces.join(","))
             }
         }
+    }
+}
+
+impl Faces {
+    pub fn min(&self) -> isize {
+        match self {
+            Faces::Standard(_) => 1,
+            Faces::Fudge => -1,
+            Faces::Range(start, _) => *start,
+            Faces::Custom(faces) => faces.iter().map(Face::value).min().unwrap_or(0),
+        }
+    }
+
+    pub fn max(&self) -> isize {
+        match self {
+            Faces::Standard(n) => *n as isize,
+            Faces::Fudge => 1,
+            Faces::Range(_, end) => *end,
+            Faces::Custom(faces) => faces.iter().map(Face::value).max().unwrap_or(0),
+        }
+    }
 
-        match self.retention {
-            RollRetention::Highest(n) => {
-                if n > self.count {
-                    panic!("cannot remove that many");
-                }
-                let mut removals = rolls.iter().map(|d| d.value).collect::<Vec<usize>>();
-                removals.sort();
-                removals = removals
+    /// The number of faces on the die.
+    fn sides(&self) -> usize {
+        match self {
+            Faces::Standard(n) => *n,
+            Faces::Fudge => 3,
+            Faces::Range(start, end) => end.abs_diff(*start).saturating_add(1),
+            Faces::Custom(faces) => faces.len(),
+        }
+    }
+
+    /// Whether any face satisfies `f`, which may only change its answer at the given
+    /// `edges`. Only the edges and extremes are checked for dice numbered in a range.
+    fn any(&self, edges: impl IntoIterator<Item = isize>, f: impl Fn(isize) -> bool) -> bool {
+        match self {
+            Faces::Custom(faces) => faces.iter().any(|face| f(face.value())),
+            faces => {
+                let range = faces.min()..=faces.max();
+                [*range.start(), *range.end()]
                     .into_iter()
-                    .take(rolls.len() - n)
-                    .collect::<Vec<usize>>();
-                rolls.iter_mut().for_each(|i| {
-                    if let Some(idx) = removals.iter().position(|j| *j == i.value) {
-                        removals.remove(idx);
-                        i.retained = false;
-                    }
-                });
+                    .chain(edges)
+                    .any(|v| range.contains(&v) && f(v))
             }
-            RollRetention::Lowest(n) => {
-                if n > self.count {
-                    panic!("cannot remove that many");
+        }
+    }
+
+    fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> RollItem {
+        let (value, symbol) = match self {
+            Faces::Custom(faces) => match &faces[rng.gen_range(0..faces.len())] {
+                Face::Number(n) => (*n, None),
+                Face::Symbol(s) => (0, Some(s.clone())),
+            },
+            _ => (rng.gen_range(self.min()..=self.max()), None),
+        };
+
+        RollItem {
+            value,
+            retained: true,
+            quality: RollQuality::Regular,
+            successes: None,
+            critical: None,
+            symbol,
+            reason: None,
+        }
+    }
+
+    /// How a rolled face is shown to players.
+    pub fn label(&self, value: isize) -> String {
+        match self {
+            Faces::Fudge => match value {
+                v if v > 0 => "+".to_string(),
+                v if v < 0 => "-".to_string(),
+                _ => " ".to_string(),
+            },
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dice {
+    pub faces: Faces,
+    pub count: usize,
+    pub retention: RollRetention,
+    pub modifiers: Vec<RollModifier>,
+}
+
+impl Display for Dice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}d{}{}", self.count, self.faces, self.suffix())
+    }
+}
+
+impl Dice {
+    /// The retention and modifiers of the dice in standard notation, as written after
+    /// the count and faces.
+    fn suffix(&self) -> String {
+        let ret_str = self.retention.to_string();
+
+        let mod_str = self
+            .modifiers
+            .iter()
+            .map(|m| match m {
+                RollModifier::Explode {
+                    compare,
+                    kind,
+                    limit,
+                } => {
+                    let kind = match kind {
+                        Explosion::Standard => "",
+                        Explosion::Compound => "!",
+                        Explosion::Penetrate => "p",
+                    };
+                    let compare = match compare {
+                        ComparePoint::AtLeast(n) if *n == self.faces.max() => String::new(),
+                        ComparePoint::AtLeast(n) if *n > 0 => n.to_string(),
+                        compare => compare.to_string(),
+                    };
+                    let limit = limit.map(|n| format!(":{}", n)).unwrap_or_default();
+                    format!("!{}{}{}", kind, compare, limit)
                 }
-                let mut removals = rolls.iter().map(|d| d.value).collect::<Vec<usize>>();
-                removals.sort();
-                removals.reverse();
-                removals = removals
-                    .into_iter()
-                    .take(rolls.len() - n)
-                    .collect::<Vec<usize>>();
-                rolls.iter_mut().for_each(|i| {
-                    if let Some(n) = removals.iter().position(|n| *n == i.value) {
-                        removals.remove(n);
-                        i.retained = false;
+                RollModifier::Reroll { compare, once } => {
+                    let r = if *once { "ro" } else { "r" };
+                    match compare {
+                        ComparePoint::Equal(n) => format!("{}{}", r, n),
+                        compare => format!("{}{}", r, compare),
                     }
+                }
+                RollModifier::Success(compare) => compare.to_string(),
+                RollModifier::Failure(ComparePoint::Equal(n)) => format!("f{}", n),
+                RollModifier::Failure(compare) => format!("f{}", compare),
+                RollModifier::DoubleSuccess(ComparePoint::Equal(n)) => format!("ds{}", n),
+                RollModifier::DoubleSuccess(compare) => format!("ds{}", compare),
+                RollModifier::CriticalSuccess(ComparePoint::Equal(n)) => format!("cs{}", n),
+                RollModifier::CriticalSuccess(compare) => format!("cs{}", compare),
+                RollModifier::CriticalFailure(ComparePoint::Equal(n)) => format!("cf{}", n),
+                RollModifier::CriticalFailure(compare) => format!("cf{}", compare),
+            })
+            .collect::<String>();
+
+        format!("{}{}", ret_str, mod_str)
+    }
+
+    pub fn validate(&self) -> Result<(), RollError> {
+        if self.count == 0 {
+            return Err(RollError::NoDice);
+        }
+        match self.faces {
+            Faces::Standard(0) => return Err(RollError::NoFaces),
+            Faces::Standard(n) if n > isize::MAX as usize => return Err(RollError::Overflow),
+            Faces::Range(start, end) if start > end => return Err(RollError::NoFaces),
+            Faces::Custom(ref faces) if faces.is_empty() => return Err(RollError::NoFaces),
+            _ => {}
+        }
+        if self.count > MAX_DICE {
+            return Err(RollError::TooManyDice {
+                count: self.count,
+                max: MAX_DICE,
+            });
+        }
+
+        self.retention.validate(self.count)?;
+
+        let edges = self
+            .modifiers
+            .iter()
+            .flat_map(|m| match m {
+                RollModifier::Reroll { compare, .. } => compare.edges().to_vec(),
+                _ => Vec::new(),
+            })
+            .collect::<Vec<isize>>();
+        if !self.faces.any(edges.clone(), |v| !self.rerolls(v, false)) {
+            return Err(RollError::RerollsEveryFace {
+                faces: self.faces.clone(),
+            });
+        }
+
+        if let Some((compare, _, _)) = self.explosion() {
+            // Only faces that survive `r` rerolls can explode, and exploding on every one
+            // of them would only ever stop at the limit.
+            let edges = edges.iter().copied().chain(compare.edges());
+            let settled = |v| !self.rerolls(v, false);
+            if !self
+                .faces
+                .any(edges.clone(), |v| settled(v) && compare.matches(v))
+                || !self.faces.any(edges, |v| settled(v) && !compare.matches(v))
+            {
+                return Err(RollError::ExplodeOutOfRange {
+                    compare,
+                    faces: self.faces.clone(),
                 });
             }
-            RollRetention::All => {}
         }
 
-        let mut total: isize = rolls.iter().fold(0, |acc, curr| {
-            if curr.retained {
-                acc + curr.value as isize
-            } else {
-                acc
-            }
-        });
+        if self.target().is_none()
+            && self
+                .modifiers
+                .iter()
+                .any(|m| matches!(m, RollModifier::Failure(_) | RollModifier::DoubleSuccess(_)))
+        {
+            return Err(RollError::NoSuccessTarget);
+        }
+
+        Ok(())
+    }
+
+    /// The comparison point, kind and depth limit of the dice's explosion, if any.
+    fn explosion(&self) -> Option<(ComparePoint, Explosion, usize)> {
+        self.modifiers.iter().find_map(|m| match m {
+            RollModifier::Explode {
+                compare,
+                kind,
+                limit,
+            } => Some((*compare, *kind, limit.unwrap_or(MAX_EXPLOSIONS))),
+            _ => None,
+        })
+    }
+
+    /// The comparison point a die must match to count as a success, if the dice count
+    /// successes.
+    fn target(&self) -> Option<ComparePoint> {
+        self.modifiers.iter().find_map(|m| match m {
+            RollModifier::Success(compare) => Some(*compare),
+            _ => None,
+        })
+    }
+
+    /// How many successes a die showing `value` is worth, when counting successes. A
+    /// success outweighs a failure if a face matches both.
+    fn successes(&self, value: isize) -> isize {
+        let scores = self
+            .modifiers
+            .iter()
+            .map(|m| match m {
+                RollModifier::DoubleSuccess(compare) if compare.matches(value) => 2,
+                RollModifier::Success(compare) if compare.matches(value) => 1,
+                RollModifier::Failure(compare) if compare.matches(value) => -1,
+                _ => 0,
+            })
+            .collect::<Vec<isize>>();
 
+        match scores.iter().max() {
+            Some(n) if *n > 0 => *n,
+            _ => scores.iter().min().copied().unwrap_or(0),
+        }
+    }
+
+    /// The quality of a die showing `value`. Critical successes and failures are the
+    /// faces matching any `cs` and `cf` modifiers, or by default the highest and lowest
+    /// faces.
+    fn quality(&self, value: isize) -> RollQuality {
+        let mut critical = None;
+        let mut fumble = None;
         for m in self.modifiers.iter() {
             match m {
-                RollModifier::Add(n) => total += *n as isize,
-                RollModifier::Subtract(n) => total -= *n as isize,
-                RollModifier::Multiply(n) => total *= *n as isize,
-                RollModifier::Divide(n) => total /= *n as isize,
+                RollModifier::CriticalSuccess(compare) => {
+                    critical = Some(critical.unwrap_or(false) || compare.matches(value))
+                }
+                RollModifier::CriticalFailure(compare) => {
+                    fumble = Some(fumble.unwrap_or(false) || compare.matches(value))
+                }
                 _ => {}
             }
         }
 
-        let mod_str = self
+        // Faces set explicitly take precedence over the defaults.
+        match (critical, fumble) {
+            (Some(true), _) => RollQuality::Good,
+            (_, Some(true)) => RollQuality::Bad,
+            (None, _) if value == self.faces.max() => RollQuality::Good,
+            (_, None) if value == self.faces.min() => RollQuality::Bad,
+            _ => RollQuality::Regular,
+        }
+    }
+
+    /// Rolls a single face, without any rerolls.
+    fn roll_face<R: Rng + ?Sized>(&self, rng: &mut R) -> RollItem {
+        let mut roll = self.faces.roll(rng);
+        if roll.symbol.is_none() {
+            roll.quality = self.quality(roll.value);
+        }
+        roll
+    }
+
+    /// Whether a die showing `value` is rerolled, by a modifier that rerolls until the
+    /// face no longer matches, or by one that rerolls only once.
+    fn rerolls(&self, value: isize, once: bool) -> bool {
+        self.modifiers.iter().any(|m| {
+            matches!(m, RollModifier::Reroll { compare, once: o } if *o == once && compare.matches(value))
+        })
+    }
+
+    /// Rolls one face, rerolling it until it no longer matches any `r` modifier.
+    /// Rerolled faces are kept in `rolls`, marked as not retained.
+    fn settle<R: Rng + ?Sized>(&self, rng: &mut R, rolls: &mut Vec<RollItem>) -> RollItem {
+        let mut roll = self.roll_face(rng);
+        while self.rerolls(roll.value, false) {
+            roll.retained = false;
+            roll.reason = Some(DiscardReason::Rerolled);
+            rolls.push(std::mem::replace(&mut roll, self.roll_face(rng)));
+        }
+        roll
+    }
+
+    /// Rolls one die, applying any rerolls. A settled face matching an `ro` modifier is
+    /// rerolled once, and that roll is settled in turn.
+    fn roll_die<R: Rng + ?Sized>(&self, rng: &mut R, rolls: &mut Vec<RollItem>) -> RollItem {
+        let mut roll = self.settle(rng, rolls);
+        if self.rerolls(roll.value, true) {
+            roll.retained = false;
+            roll.reason = Some(DiscardReason::Rerolled);
+            rolls.push(roll);
+            roll = self.settle(rng, rolls);
+        }
+        roll
+    }
+
+    fn roll_term<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<TermResult, RollError> {
+        let mut rolls: Vec<RollItem> = Vec::with_capacity(self.count);
+
+        let explosion = self.explosion();
+
+        for _ in 0..self.count {
+            let mut roll = self.roll_die(rng, &mut rolls);
+
+            if let Some((compare, kind, limit)) = explosion {
+                let mut face = roll.value;
+                for _ in 0..limit {
+                    if !compare.matches(face) {
+                        break;
+                    }
+                    let mut extra = self.roll_die(rng, &mut rolls);
+                    face = extra.value;
+                    match kind {
+                        Explosion::Standard => rolls.push(std::mem::replace(&mut roll, extra)),
+                        Explosion::Compound => {
+                            roll.value = roll
+                                .value
+                                .checked_add(extra.value)
+                                .ok_or(RollError::Overflow)?;
+                        }
+                        Explosion::Penetrate => {
+                            extra.value = extra.value.checked_sub(1).ok_or(RollError::Overflow)?;
+                            rolls.push(std::mem::replace(&mut roll, extra));
+                        }
+                    }
+                }
+            }
+
+            rolls.push(roll);
+        }
+
+        let kept = (0..rolls.len())
+            .filter(|i| rolls[*i].retained)
+            .collect::<Vec<usize>>();
+        let values = kept.iter().map(|i| rolls[*i].value).collect::<Vec<isize>>();
+        for (i, retained) in kept.into_iter().zip(self.retention.retained(&values)) {
+            if !retained {
+                rolls[i].retained = false;
+                rolls[i].reason = Some(DiscardReason::Dropped);
+            }
+        }
+
+        // Without `cs` or `cf`, the quality of a die counting successes is whether it
+        // succeeded, and its critical quality is kept aside.
+        let criticals = self.modifiers.iter().any(|m| {
+            matches!(
+                m,
+                RollModifier::CriticalSuccess(_) | RollModifier::CriticalFailure(_)
+            )
+        });
+        let successes = self.target().map(|_| {
+            let mut successes = 0isize;
+            for r in rolls.iter_mut() {
+                let n = self.successes(r.value);
+                r.successes = Some(n);
+                if !criticals {
+                    let quality = match n {
+                        n if n > 0 => RollQuality::Good,
+                        n if n < 0 => RollQuality::Bad,
+                        _ => RollQuality::Regular,
+                    };
+                    r.critical = Some(std::mem::replace(&mut r.quality, quality));
+                }
+                if r.retained {
+                    successes += n;
+                }
+            }
+            successes
+        });
+
+        let total = match successes {
+            Some(n) => n,
+            None => rolls.iter().try_fold(0isize, |acc, curr| {
+                if curr.retained {
+                    acc.checked_add(curr.value).ok_or(RollError::Overflow)
+                } else {
+                    Ok(acc)
+                }
+            })?,
+        };
+
+        Ok(TermResult {
+            input: self.to_string(),
+            total,
+            faces: self.faces.clone(),
+            rolls,
+            successes,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RollExpression {
+    Constant(usize),
+    Dice(Dice),
+    Nested(NestedDice),
+    Group(Group),
+    Negate(Box<RollExpression>),
+    Binary(Operator, Box<RollExpression>, Box<RollExpression>),
+    /// A named value, such as `@str`, looked up in the [`Environment`] when rolled.
+    Variable(String),
+    Function(Function, Vec<RollExpression>),
+    If(Conditional),
+}
+
+/// Values bound to the variables of an expression, such as the numbers on a character
+/// sheet.
+pub type Environment = HashMap<String, isize>;
+
+/// Compares the totals of two expressions in a [`Conditional`]. Unlike a
+/// [`ComparePoint`], `<` and `>` are strict here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Comparison {
+    Less,
+    AtMost,
+    Equal,
+    NotEqual,
+    AtLeast,
+    Greater,
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Comparison::Less => "<",
+            Comparison::AtMost => "<=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::AtLeast => ">=",
+            Comparison::Greater => ">",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Comparison {
+    pub fn matches(&self, lhs: isize, rhs: isize) -> bool {
+        match self {
+            Comparison::Less => lhs < rhs,
+            Comparison::AtMost => lhs <= rhs,
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::AtLeast => lhs >= rhs,
+            Comparison::Greater => lhs > rhs,
+        }
+    }
+}
+
+/// Rolls one of two branches depending on a condition, such as
+/// `if(1d20+5>=15, 2d6, 1d6)`. Only the chosen branch is rolled.
+///
+/// A condition that compares nothing passes when its total is not zero. A comparison
+/// written straight after dice, as in `1d20>=15` or `1d6!=6`, compares the roll rather
+/// than counting successes or exploding, so success targets in a condition need
+/// parentheses, as in `if((3d6>5)>=2, 1, 0)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conditional {
+    pub condition: Box<RollExpression>,
+    pub comparison: Option<(Comparison, Box<RollExpression>)>,
+    pub then: Box<RollExpression>,
+    pub otherwise: Box<RollExpression>,
+}
+
+/// Whether `expression` reads the same at the start of a condition, where dice take no
+/// success targets or exploding comparison points.
+fn reads_as_condition(expression: &RollExpression) -> bool {
+    let plain = |dice: &Dice| {
+        !dice
             .modifiers
             .iter()
-            .map(|m| match m {
-                RollModifier::Add(n) => format!("+{}", n),
-                RollModifier::Subtract(n) => format!("-{}", n),
-                RollModifier::Multiply(n) => format!("x{}", n),
-                RollModifier::Divide(n) => format!("/{}", n),
-                RollModifier::Explode(n) => {
-                    if *n != self.faces {
-                        format!("!{}", n)
-                    } else {
-                        "!".to_string()
-                    }
+            .any(|m| matches!(m, RollModifier::Success(_) | RollModifier::Explode { .. }))
+    };
+    match expression {
+        RollExpression::Dice(dice) => plain(dice),
+        RollExpression::Nested(nested) => plain(&nested.dice),
+        RollExpression::Negate(inner) => reads_as_condition(inner),
+        RollExpression::Binary(_, lhs, rhs) => reads_as_condition(lhs) && reads_as_condition(rhs),
+        _ => true,
+    }
+}
+
+/// Writes the conditional in canonical standard notation. The condition is wrapped in
+/// parentheses when its dice have success targets or explode, which would otherwise
+/// read differently in a condition.
+impl Display for Conditional {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.comparison, reads_as_condition(&self.condition)) {
+            (Some((comparison, rhs)), true) => {
+                write!(f, "if({}{}{}", self.condition, comparison, rhs)?
+            }
+            (Some((comparison, rhs)), false) => {
+                write!(f, "if(({}){}{}", self.condition, comparison, rhs)?
+            }
+            (None, true) => write!(f, "if({}", self.condition)?,
+            (None, false) => write!(f, "if(({})", self.condition)?,
+        }
+        write!(f, ",{},{})", self.then, self.otherwise)
+    }
+}
+
+impl Conditional {
+    pub fn validate(&self) -> Result<(), RollError> {
+        self.condition.validate()?;
+        if let Some((_, rhs)) = &self.comparison {
+            rhs.validate()?;
+        }
+        self.then.validate()?;
+        self.otherwise.validate()
+    }
+
+    fn evaluate<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<isize, RollError> {
+        let total = self.condition.evaluate(rng, env, result)?;
+        let compared = match &self.comparison {
+            Some((comparison, rhs)) => Some((*comparison, rhs.evaluate(rng, env, result)?)),
+            None => None,
+        };
+        let passed = match compared {
+            Some((comparison, rhs)) => comparison.matches(total, rhs),
+            None => total != 0,
+        };
+
+        let branch = if passed { &self.then } else { &self.otherwise };
+        result.conditions.push(ConditionResult {
+            input: self.to_string(),
+            total,
+            compared,
+            passed,
+            branch: branch.to_string(),
+        });
+        branch.evaluate(rng, env, result)
+    }
+}
+
+/// Dice whose count or sides are rolled before the dice themselves, such as `(1d4)d6`
+/// or `2d(1d4*2)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NestedDice {
+    /// Rolled for the number of dice, in place of the count of `dice`.
+    pub count: Option<Box<RollExpression>>,
+    /// Rolled for the number of sides, in place of the faces of `dice`.
+    pub sides: Option<Box<RollExpression>>,
+    /// The dice to roll once the count and sides are known. When the sides are rolled,
+    /// its faces are ignored, and an explosion on `>0` explodes on the highest side.
+    pub dice: Dice,
+}
+
+impl Display for NestedDice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.count {
+            Some(count) => write!(f, "({})", count)?,
+            None => write!(f, "{}", self.dice.count)?,
+        }
+        match &self.sides {
+            Some(sides) => write!(f, "d({})", sides)?,
+            None => write!(f, "d{}", self.dice.faces)?,
+        }
+        write!(f, "{}", self.dice.suffix())
+    }
+}
+
+impl NestedDice {
+    /// Checks the rolled count and sides. The dice themselves can only be checked
+    /// once those are known, by [`NestedDice::resolve`].
+    pub fn validate(&self) -> Result<(), RollError> {
+        for expression in self.count.iter().chain(self.sides.iter()) {
+            expression.validate()?;
+        }
+        Ok(())
+    }
+
+    /// The dice to roll for a rolled count and number of sides. A rolled count below one
+    /// rolls no dice, totalling zero.
+    pub fn resolve(&self, count: Option<isize>, sides: Option<isize>) -> Result<Dice, RollError> {
+        let mut dice = self.dice.clone();
+        if let Some(count) = count {
+            dice.count = usize::try_from(count).unwrap_or(0);
+        }
+        if let Some(sides) = sides {
+            dice.faces = Faces::Standard(usize::try_from(sides).map_err(|_| RollError::NoFaces)?);
+            for modifier in dice.modifiers.iter_mut() {
+                if let RollModifier::Explode {
+                    compare: compare @ ComparePoint::AtLeast(0),
+                    ..
+                } = modifier
+                {
+                    *compare = ComparePoint::AtLeast(sides);
                 }
-            })
-            .collect::<String>();
+            }
+        }
+        if dice.count == 0 {
+            // The faces and modifiers are still checked, but there is nothing to keep.
+            Dice {
+                count: 1,
+                retention: RollRetention::All,
+                ..dice.clone()
+            }
+            .validate()?;
+        } else {
+            dice.validate()?;
+        }
+        Ok(dice)
+    }
 
-        let ret_str = match self.retention {
-            RollRetention::All => String::new(),
-            RollRetention::Highest(n) => format!("h{}", n),
-            RollRetention::Lowest(n) => format!("l{}", n),
+    fn evaluate<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<isize, RollError> {
+        let count = match &self.count {
+            Some(count) => Some(count.evaluate(rng, env, result)?),
+            None => None,
         };
+        let sides = match &self.sides {
+            Some(sides) => Some(sides.evaluate(rng, env, result)?),
+            None => None,
+        };
+        let dice = self.resolve(count, sides)?;
+
+        let term = dice.roll_term(rng)?;
+        let total = term.total;
+        result.terms.push(term);
+        result.nested.push(NestedResult {
+            input: self.to_string(),
+            count: dice.count,
+            faces: dice.faces,
+        });
+        Ok(total)
+    }
+}
+
+/// Several expressions rolled together, such as `{4d6, 3d8}h1`, totalling the members
+/// kept by the retention.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Group {
+    pub members: Vec<RollExpression>,
+    pub retention: RollRetention,
+}
 
-        let input = format!("{}d{}{}{}", self.count, self.faces, ret_str, mod_str);
+impl Display for Group {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let members = self
+            .members
+            .iter()
+            .map(|m| m.to_string())
+            .collect::<Vec<String>>();
+        write!(f, "{{{}}}{}", members.join(","), self.retention)
+    }
+}
+
+impl Group {
+    pub fn validate(&self) -> Result<(), RollError> {
+        if self.members.is_empty() {
+            return Err(RollError::NoDice);
+        }
+        for member in self.members.iter() {
+            member.validate()?;
+        }
+        self.retention.validate(self.members.len())
+    }
+
+    fn evaluate<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<isize, RollError> {
+        let mut totals = Vec::with_capacity(self.members.len());
+        let mut terms = Vec::with_capacity(self.members.len());
+        for member in self.members.iter() {
+            let start = result.terms.len();
+            totals.push(member.evaluate(rng, env, result)?);
+            terms.push(start..result.terms.len());
+        }
 
-        RollResult {
+        let retained = self.retention.retained(&totals);
+        for (terms, _) in terms.into_iter().zip(retained.iter()).filter(|(_, r)| !**r) {
+            for r in result.terms[terms]
+                .iter_mut()
+                .flat_map(|t| t.rolls.iter_mut())
+            {
+                if r.retained {
+                    r.retained = false;
+                    r.reason = Some(DiscardReason::Dropped);
+                }
+            }
+        }
+
+        let total = totals
+            .iter()
+            .zip(retained.iter())
+            .filter(|(_, r)| **r)
+            .try_fold(0isize, |acc, (t, _)| {
+                acc.checked_add(*t).ok_or(RollError::Overflow)
+            })?;
+
+        result.groups.push(GroupResult {
+            input: self.to_string(),
             total,
-            rolls,
-            input,
+            members: self
+                .members
+                .iter()
+                .zip(totals)
+                .zip(retained)
+                .map(|((m, total), retained)| GroupMember {
+                    input: m.to_string(),
+                    total,
+                    retained,
+                })
+                .collect(),
+        });
+        Ok(total)
+    }
+}
+
+/// Writes the expression in canonical standard notation, such that parsing the output
+/// with [`standard::StandardNotation`] gives back an equal expression.
+impl Display for RollExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RollExpression::Constant(n) => write!(f, "{}", n),
+            RollExpression::Dice(dice) => write!(f, "{}", dice),
+            RollExpression::Nested(nested) => write!(f, "{}", nested),
+            RollExpression::If(conditional) => write!(f, "{}", conditional),
+            RollExpression::Group(group) => write!(f, "{}", group),
+            RollExpression::Variable(name) => write!(f, "@{}", name),
+            RollExpression::Function(function, args) => {
+                let args = args.iter().map(|a| a.to_string()).collect::<Vec<String>>();
+                write!(f, "{}({})", function, args.join(","))
+            }
+            RollExpression::Negate(inner) => match inner.as_ref() {
+                RollExpression::Binary(..) | RollExpression::Negate(_) => write!(f, "-({})", inner),
+                _ => write!(f, "-{}", inner),
+            },
+            RollExpression::Binary(op, lhs, rhs) => {
+                if lhs.precedence() < op.precedence() {
+                    write!(f, "({})", lhs)?;
+                } else {
+                    write!(f, "{}", lhs)?;
+                }
+                write!(f, "{}", op.symbol())?;
+                // Operators are left-associative, so an equal-precedence right operand
+                // must keep its parentheses. Negations are wrapped for readability.
+                if rhs.precedence() <= op.precedence()
+                    || matches!(rhs.as_ref(), RollExpression::Negate(_))
+                {
+                    write!(f, "({})", rhs)
+                } else {
+                    write!(f, "{}", rhs)
+                }
+            }
+        }
+    }
+}
+
+impl RollExpression {
+    fn precedence(&self) -> u8 {
+        match self {
+            RollExpression::Binary(op, _, _) => op.precedence(),
+            _ => u8::MAX,
+        }
+    }
+
+    /// Checks the expression for problems that would prevent it from ever being rolled.
+    /// Errors that depend on the dice, such as dividing by a roll of zero, are only
+    /// caught by [`TryRoll::try_roll`].
+    pub fn validate(&self) -> Result<(), RollError> {
+        match self {
+            RollExpression::Constant(_) | RollExpression::Variable(_) => Ok(()),
+            RollExpression::Dice(dice) => dice.validate(),
+            RollExpression::Nested(nested) => nested.validate(),
+            RollExpression::If(conditional) => conditional.validate(),
+            RollExpression::Group(group) => group.validate(),
+            RollExpression::Negate(inner) => inner.validate(),
+            RollExpression::Function(function, args) => function.validate(args),
+            RollExpression::Binary(op, lhs, rhs) => {
+                lhs.validate()?;
+                rhs.validate()?;
+                match (op, rhs.as_ref()) {
+                    (Operator::Divide, RollExpression::Constant(0)) => Err(RollError::DivideByZero),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+
+    /// Rolls the expression, recording every term and group in `result`, and returns
+    /// the total.
+    fn evaluate<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<isize, RollError> {
+        match self {
+            RollExpression::Constant(n) => isize::try_from(*n).map_err(|_| RollError::Overflow),
+            RollExpression::Dice(dice) => {
+                let term = dice.roll_term(rng)?;
+                let total = term.total;
+                result.terms.push(term);
+                Ok(total)
+            }
+            RollExpression::Nested(nested) => nested.evaluate(rng, env, result),
+            RollExpression::If(conditional) => conditional.evaluate(rng, env, result),
+            RollExpression::Group(group) => group.evaluate(rng, env, result),
+            RollExpression::Negate(inner) => inner
+                .evaluate(rng, env, result)?
+                .checked_neg()
+                .ok_or(RollError::Overflow),
+            RollExpression::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(rng, env, result)?;
+                let rhs = rhs.evaluate(rng, env, result)?;
+                op.apply(lhs, rhs)
+            }
+            RollExpression::Variable(name) => env
+                .get(name)
+                .copied()
+                .ok_or_else(|| RollError::UnboundVariable(name.clone())),
+            RollExpression::Function(function, args) => function.evaluate(args, rng, env, result),
+        }
+    }
+
+    /// Rolls the expression like [`RollExpression::evaluate`], but keeps the exact result
+    /// of any division not already rounded by a group or function.
+    fn evaluate_exact<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        env: &Environment,
+        result: &mut RollResult,
+    ) -> Result<Fraction, RollError> {
+        match self {
+            RollExpression::Negate(inner) => {
+                Fraction::from(0).apply(Operator::Subtract, inner.evaluate_exact(rng, env, result)?)
+            }
+            RollExpression::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate_exact(rng, env, result)?;
+                let rhs = rhs.evaluate_exact(rng, env, result)?;
+                lhs.apply(*op, rhs)
+            }
+            _ => Ok(Fraction::from(self.evaluate(rng, env, result)?)),
+        }
+    }
+
+    /// Replaces every variable with its value in `env`, giving an expression that can
+    /// be rolled, simulated or analyzed without an environment.
+    pub fn bind(&self, env: &Environment) -> Result<RollExpression, RollError> {
+        Ok(match self {
+            RollExpression::Variable(name) => match env.get(name) {
+                Some(n) if *n < 0 => {
+                    RollExpression::Negate(Box::new(RollExpression::Constant(n.unsigned_abs())))
+                }
+                Some(n) => RollExpression::Constant(n.unsigned_abs()),
+                None => return Err(RollError::UnboundVariable(name.clone())),
+            },
+            RollExpression::Group(group) => RollExpression::Group(Group {
+                members: group
+                    .members
+                    .iter()
+                    .map(|m| m.bind(env))
+                    .collect::<Result<_, _>>()?,
+                retention: group.retention.clone(),
+            }),
+            RollExpression::Nested(nested) => RollExpression::Nested(NestedDice {
+                count: match &nested.count {
+                    Some(count) => Some(Box::new(count.bind(env)?)),
+                    None => None,
+                },
+                sides: match &nested.sides {
+                    Some(sides) => Some(Box::new(sides.bind(env)?)),
+                    None => None,
+                },
+                dice: nested.dice.clone(),
+            }),
+            RollExpression::If(conditional) => RollExpression::If(Conditional {
+                condition: Box::new(conditional.condition.bind(env)?),
+                comparison: match &conditional.comparison {
+                    Some((comparison, rhs)) => Some((*comparison, Box::new(rhs.bind(env)?))),
+                    None => None,
+                },
+                then: Box::new(conditional.then.bind(env)?),
+                otherwise: Box::new(conditional.otherwise.bind(env)?),
+            }),
+            RollExpression::Negate(inner) => RollExpression::Negate(Box::new(inner.bind(env)?)),
+            RollExpression::Function(function, args) => RollExpression::Function(
+                *function,
+                args.iter().map(|a| a.bind(env)).collect::<Result<_, _>>()?,
+            ),
+            RollExpression::Binary(op, lhs, rhs) => {
+                RollExpression::Binary(*op, Box::new(lhs.bind(env)?), Box::new(rhs.bind(env)?))
+            }
+            RollExpression::Constant(_) | RollExpression::Dice(_) => self.clone(),
+        })
+    }
+}
+
+/// An expression rolled several times independently, such as `6#4d6h3` for a set of
+/// ability scores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Repeat {
+    pub count: usize,
+    pub expression: RollExpression,
+}
+
+/// Writes the repeat in canonical standard notation, leaving out a count of one.
+impl Display for Repeat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.count == 1 {
+            write!(f, "{}", self.expression)
+        } else {
+            write!(f, "{}#{}", self.count, self.expression)
         }
     }
 }
 
+impl Repeat {
+    pub fn validate(&self) -> Result<(), RollError> {
+        if self.count == 0 {
+            return Err(RollError::NoDice);
+        }
+        if self.count > MAX_REPEATS {
+            return Err(RollError::TooManyRepeats {
+                count: self.count,
+                max: MAX_REPEATS,
+            });
+        }
+        self.expression.validate()
+    }
+
+    /// Rolls the expression `count` times using the thread-local RNG.
+    pub fn try_roll(&self) -> Result<Vec<RollResult>, RollError> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    /// Rolls the expression `count` times using the provided RNG.
+    pub fn try_roll_with<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<Vec<RollResult>, RollError> {
+        self.validate()?;
+        (0..self.count)
+            .map(|_| self.expression.try_roll_with(rng))
+            .collect()
+    }
+}
+
+pub trait Roll {
+    /// Rolls the expression using the thread-local RNG.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression is invalid or evaluation fails. Use [`TryRoll::try_roll`]
+    /// to handle these cases as errors.
+    fn roll(&self) -> RollResult {
+        self.roll_with(&mut rand::thread_rng())
+    }
+
+    /// Rolls the expression using the provided RNG. Seeding the RNG makes the result
+    /// reproducible.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the expression is invalid or evaluation fails. Use
+    /// [`TryRoll::try_roll_with`] to handle these cases as errors.
+    fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult;
+}
+
+pub trait TryRoll {
+    fn try_roll(&self) -> Result<RollResult, RollError> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    fn try_roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<RollResult, RollError> {
+        self.try_roll_in(&Environment::new(), rng)
+    }
+
+    /// Rolls the expression using the provided RNG, looking up variables in `env`.
+    fn try_roll_in<R: Rng + ?Sized>(
+        &self,
+        env: &Environment,
+        rng: &mut R,
+    ) -> Result<RollResult, RollError>;
+}
+
+impl Roll for RollExpression {
+    fn roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        match self.try_roll_with(rng) {
+            Ok(result) => result,
+            Err(e) => panic!("cannot roll {}: {}", self, e),
+        }
+    }
+}
+
+impl TryRoll for RollExpression {
+    fn try_roll_in<R: Rng + ?Sized>(
+        &self,
+        env: &Environment,
+        rng: &mut R,
+    ) -> Result<RollResult, RollError> {
+        let mut result = RollResult {
+            input: self.to_string(),
+            total: 0,
+            terms: Vec::new(),
+            groups: Vec::new(),
+            nested: Vec::new(),
+            conditions: Vec::new(),
+        };
+
+        self.validate()?;
+        result.total = self.evaluate(rng, env, &mut result)?;
+
+        Ok(result)
+    }
+}
+
 pub trait Notation {
-    fn parse_from_str(input: &str) -> Result<Vec<RollExpression>, Error>;
+    /// Parses every roll in `input`, keeping each repeated roll together.
+    fn parse_repeats(input: &str) -> Result<Vec<Repeat>, Error>;
+
+    /// Parses every roll in `input`. A repeated roll, such as `6#4d6h3`, gives one
+    /// expression per repetition.
+    fn parse_from_str(input: &str) -> Result<Vec<RollExpression>, Error> {
+        Ok(Self::parse_repeats(input)?
+            .into_iter()
+            .flat_map(|r| vec![r.expression; r.count])
+            .collect())
+    }
 }
@@ -0,0 +1,231 @@
+//! Dice pools for Shadowrun, written `12sr` for twelve dice, `12sr4` for a limit of 4,
+//! or `12sr!` to use Edge.
+//!
+//! Pools are rolled as d6s counting a hit on each 5 or 6. Ones are critical failures,
+//! [`RollQuality::Bad`](crate::RollQuality::Bad) in the [`RollResult`], so a glitch
+//! shows up among the dice.
+
+use crate::{
+    ComparePoint, Dice, Explosion, Faces, RollError, RollExpression, RollModifier, RollResult,
+    RollRetention, TryRoll,
+};
+use rand::Rng;
+use std::fmt::Display;
+
+/// The lowest face that counts as a hit.
+const HIT: isize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pool {
+    pub dice: usize,
+    /// The most hits that count, if any.
+    pub limit: Option<usize>,
+    /// Whether Edge is used, which rolls sixes again by the rule of six and ignores the
+    /// limit.
+    pub edge: bool,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Pool {
+            dice: 1,
+            limit: None,
+            edge: false,
+        }
+    }
+}
+
+/// Writes the pool in standard notation.
+impl Display for Pool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}sr", self.dice)?;
+        if let Some(limit) = self.limit {
+            write!(f, "{}", limit)?;
+        }
+        if self.edge {
+            write!(f, "!")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Glitch {
+    None,
+    /// More than half the dice rolled were ones.
+    Glitch,
+    /// A glitch without a single hit.
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolResult {
+    /// Every die rolled, with the hits that count after the limit as the total.
+    pub roll: RollResult,
+    /// The hits that count, after the limit.
+    pub hits: usize,
+    pub glitch: Glitch,
+}
+
+/// Writes the dice with the hits that count after the limit, rather than every success
+/// rolled.
+impl Display for PoolResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut roll = self.roll.clone();
+        for term in roll.terms.iter_mut() {
+            term.successes = None;
+        }
+        write!(f, "{}", roll)?;
+        match self.hits {
+            1 => write!(f, " (1 hit)")?,
+            n => write!(f, " ({} hits)", n)?,
+        }
+        match self.glitch {
+            Glitch::None => Ok(()),
+            Glitch::Glitch => write!(f, " glitch"),
+            Glitch::Critical => write!(f, " critical glitch"),
+        }
+    }
+}
+
+impl Pool {
+    /// The dice rolled for the pool.
+    pub fn dice(&self) -> Dice {
+        let mut modifiers = Vec::new();
+        if self.edge {
+            modifiers.push(RollModifier::Explode {
+                compare: ComparePoint::AtLeast(6),
+                kind: Explosion::Standard,
+                limit: None,
+            });
+        }
+        modifiers.push(RollModifier::Success(ComparePoint::AtLeast(HIT)));
+        modifiers.push(RollModifier::CriticalFailure(ComparePoint::Equal(1)));
+
+        Dice {
+            faces: Faces::Standard(6),
+            count: self.dice,
+            retention: RollRetention::All,
+            modifiers,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), RollError> {
+        self.dice().validate()
+    }
+
+    pub fn try_roll(&self) -> Result<PoolResult, RollError> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    pub fn try_roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<PoolResult, RollError> {
+        let mut roll = RollExpression::Dice(self.dice()).try_roll_with(rng)?;
+        roll.input = self.to_string();
+
+        let ones = roll.rolls().filter(|r| r.value == 1).count();
+        let rolled = roll.rolls().count();
+
+        let hits = roll.successes().unwrap_or(0).unsigned_abs();
+        let glitch = match (ones * 2 > rolled, hits) {
+            (false, _) => Glitch::None,
+            (true, 0) => Glitch::Critical,
+            (true, _) => Glitch::Glitch,
+        };
+
+        let hits = match self.limit {
+            Some(limit) if !self.edge => hits.min(limit),
+            _ => hits,
+        };
+        roll.total = isize::try_from(hits).map_err(|_| RollError::Overflow)?;
+
+        Ok(PoolResult { roll, hits, glitch })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RollQuality;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    pub fn counts_hits_and_glitches() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let mut glitches = [false, false];
+        for input in ["2sr", "3sr", "8sr"] {
+            let pool: Pool = input.parse().unwrap();
+            for _ in 0..200 {
+                let result = pool.try_roll_with(&mut rng).unwrap();
+                let rolls = result.roll.rolls().collect::<Vec<_>>();
+                assert_eq!(rolls.len(), pool.dice);
+
+                let hits = rolls.iter().filter(|r| r.value >= 5).count();
+                let ones = rolls.iter().filter(|r| r.value == 1).count();
+                assert_eq!(result.hits, hits);
+                assert_eq!(result.roll.total, hits as isize);
+                assert!(rolls
+                    .iter()
+                    .all(|r| (r.quality == RollQuality::Bad) == (r.value == 1)));
+
+                let glitch = match (ones * 2 > pool.dice, hits) {
+                    (false, _) => Glitch::None,
+                    (true, 0) => Glitch::Critical,
+                    (true, _) => Glitch::Glitch,
+                };
+                assert_eq!(result.glitch, glitch);
+                glitches[0] |= glitch == Glitch::Glitch;
+                glitches[1] |= glitch == Glitch::Critical;
+            }
+        }
+        assert_eq!(glitches, [true, true]);
+    }
+
+    #[test]
+    pub fn caps_hits_at_the_limit() {
+        let mut rng = StdRng::seed_from_u64(25);
+        let pool: Pool = "20sr3".parse().unwrap();
+        for _ in 0..20 {
+            let result = pool.try_roll_with(&mut rng).unwrap();
+            let hits = result.roll.rolls().filter(|r| r.value >= 5).count();
+            assert_eq!(result.hits, hits.min(3));
+        }
+
+        colored::control::set_override(false);
+        let result = pool.try_roll_with(&mut rng).unwrap();
+        let shown = result.to_string();
+        assert!(shown.contains(" (3 hits)"), "{}", shown);
+        assert!(!shown.contains("successes"), "{}", shown);
+
+        let pool: Pool = "20sr3!".parse().unwrap();
+        let result = pool.try_roll_with(&mut rng).unwrap();
+        assert!(result.hits > 3, "Edge ignores the limit");
+    }
+
+    #[test]
+    pub fn rolls_sixes_again_with_edge() {
+        let pool: Pool = "10sr!".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(25);
+        for _ in 0..20 {
+            let result = pool.try_roll_with(&mut rng).unwrap();
+            let rolls = result.roll.rolls().collect::<Vec<_>>();
+            let sixes = rolls.iter().filter(|r| r.value == 6).count();
+            assert_eq!(rolls.len(), 10 + sixes, "every six rolls again");
+            assert_eq!(result.hits, rolls.iter().filter(|r| r.value >= 5).count());
+        }
+    }
+
+    #[test]
+    pub fn round_trips_through_display() {
+        for input in ["12sr", "12sr4", "6sr!", "6sr5!"] {
+            let pool: Pool = input.parse().unwrap();
+            assert_eq!(pool.to_string(), input);
+        }
+        assert_eq!("12SR".parse::<Pool>().unwrap().to_string(), "12sr");
+        assert!("sr".parse::<Pool>().is_err());
+        assert!("0sr".parse::<Pool>().is_err());
+        assert!("12sr0".parse::<Pool>().is_err());
+    }
+}
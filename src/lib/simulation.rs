@@ -0,0 +1,225 @@
+use super::*;
+use rand::{rngs::StdRng, SeedableRng};
+use std::collections::BTreeMap;
+
+/// Rolls are split across this many independently seeded RNG streams, each on its own
+/// thread. The count is fixed so a seeded simulation gives the same result on any machine.
+const SIMULATION_STREAMS: usize = 16;
+
+/// The z-score of a 95% confidence interval.
+pub const Z_95: f64 = 1.959964;
+
+/// Aggregated totals of many rolls of a [`RollExpression`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    samples: u64,
+    counts: BTreeMap<isize, u64>,
+}
+
+impl Simulation {
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Iterates over every total that was rolled and how often, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (isize, u64)> + '_ {
+        self.counts.iter().map(|(v, n)| (*v, *n))
+    }
+
+    pub fn min(&self) -> isize {
+        self.counts.keys().next().copied().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> isize {
+        self.counts.keys().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.iter().map(|(v, n)| v as f64 * n as f64).sum::<f64>() / self.samples as f64
+    }
+
+    /// The sample variance of the rolled totals.
+    pub fn variance(&self) -> f64 {
+        if self.samples < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        self.iter()
+            .map(|(v, n)| (v as f64 - mean).powi(2) * n as f64)
+            .sum::<f64>()
+            / (self.samples - 1) as f64
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The standard error of the mean.
+    pub fn std_error(&self) -> f64 {
+        self.std_dev() / (self.samples as f64).sqrt()
+    }
+
+    /// The interval around the mean for the given z-score, such as [`Z_95`].
+    pub fn confidence_interval(&self, z: f64) -> (f64, f64) {
+        let mean = self.mean();
+        let margin = z * self.std_error();
+        (mean - margin, mean + margin)
+    }
+
+    pub fn at_least(&self, value: isize) -> f64 {
+        self.counts.range(value..).map(|(_, n)| n).sum::<u64>() as f64 / self.samples as f64
+    }
+
+    pub fn at_most(&self, value: isize) -> f64 {
+        self.counts.range(..=value).map(|(_, n)| n).sum::<u64>() as f64 / self.samples as f64
+    }
+
+    /// The interval around an observed probability `p`, such as one returned by
+    /// [`Simulation::at_least`], for the given z-score.
+    pub fn probability_interval(&self, p: f64, z: f64) -> (f64, f64) {
+        let margin = z * (p * (1.0 - p) / self.samples as f64).sqrt();
+        ((p - margin).max(0.0), (p + margin).min(1.0))
+    }
+
+    /// The empirical distribution of the rolled totals.
+    pub fn distribution(&self) -> Distribution {
+        Distribution {
+            outcomes: self
+                .iter()
+                .map(|(v, n)| (v, n as f64 / self.samples as f64))
+                .collect(),
+        }
+    }
+}
+
+fn simulate_stream(
+    expression: &RollExpression,
+    samples: u64,
+    seed: u64,
+) -> Result<BTreeMap<isize, u64>, RollError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut counts = BTreeMap::new();
+    let env = Environment::new();
+    let mut result = RollResult {
+        input: String::new(),
+        total: 0,
+        terms: Vec::new(),
+        groups: Vec::new(),
+        nested: Vec::new(),
+        conditions: Vec::new(),
+    };
+
+    for _ in 0..samples {
+        result.terms.clear();
+        result.groups.clear();
+        result.nested.clear();
+        result.conditions.clear();
+        let total = expression.evaluate(&mut rng, &env, &mut result)?;
+        *counts.entry(total).or_insert(0) += 1;
+    }
+
+    Ok(counts)
+}
+
+/// Rolls `expression` `samples` times in parallel, keeping only a tally of the totals.
+/// Each thread rolls with its own RNG seeded from `rng`, so seeding `rng` makes the
+/// simulation reproducible.
+pub fn simulate<R: Rng + ?Sized>(
+    expression: &RollExpression,
+    samples: u64,
+    rng: &mut R,
+) -> Result<Simulation, RollError> {
+    if samples == 0 {
+        return Err(RollError::NoSamples);
+    }
+    expression.validate()?;
+
+    let streams = (0..SIMULATION_STREAMS as u64)
+        .map(|i| {
+            let share = samples / SIMULATION_STREAMS as u64
+                + u64::from(i < samples % SIMULATION_STREAMS as u64);
+            (share, rng.gen::<u64>())
+        })
+        .collect::<Vec<(u64, u64)>>();
+
+    let results = std::thread::scope(|scope| {
+        let handles = streams
+            .into_iter()
+            .map(|(share, seed)| scope.spawn(move || simulate_stream(expression, share, seed)))
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("simulation thread panicked"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut counts = BTreeMap::new();
+    for result in results {
+        for (v, n) in result? {
+            *counts.entry(v).or_insert(0) += n;
+        }
+    }
+
+    Ok(Simulation { samples, counts })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::standard::StandardNotation;
+
+    #[test]
+    pub fn approximates_the_exact_distribution() {
+        let expression = &StandardNotation::parse_from_str("4d6h3").unwrap()[0];
+        let exact = expression.distribution().unwrap();
+        let simulation = simulate(expression, 200_000, &mut StdRng::seed_from_u64(6)).unwrap();
+
+        assert_eq!(simulation.samples(), 200_000);
+        assert_eq!((simulation.min(), simulation.max()), (3, 18));
+        let (low, high) = simulation.confidence_interval(4.0);
+        assert!(low < exact.mean() && exact.mean() < high);
+        assert!((simulation.at_least(15) - exact.at_least(15)).abs() < 0.01);
+    }
+
+    #[test]
+    pub fn rerolls_as_the_exact_distribution_does() {
+        for input in ["1d6r1ro2", "1d6ro<3r1", "1d8r>7ro<2"] {
+            let expression = &StandardNotation::parse_from_str(input).unwrap()[0];
+            let exact = expression.distribution().unwrap();
+            let simulated = simulate(expression, 400_000, &mut StdRng::seed_from_u64(13)).unwrap();
+            let simulated = simulated.distribution();
+            for (v, p) in exact.iter() {
+                assert!(
+                    (simulated.probability(v) - p).abs() < 0.005,
+                    "{} rolled {} with chance {} rather than {}",
+                    input,
+                    v,
+                    simulated.probability(v),
+                    p
+                );
+            }
+            assert_eq!(
+                (simulated.min(), simulated.max()),
+                (exact.min(), exact.max())
+            );
+        }
+    }
+
+    #[test]
+    pub fn is_reproducible_from_a_seed() {
+        let expression = &StandardNotation::parse_from_str("2d20l1+1d8!").unwrap()[0];
+        let first = simulate(expression, 1_000, &mut StdRng::seed_from_u64(1)).unwrap();
+        let second = simulate(expression, 1_000, &mut StdRng::seed_from_u64(1)).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    pub fn rejects_zero_samples() {
+        let expression = &StandardNotation::parse_from_str("1d6").unwrap()[0];
+        assert_eq!(
+            simulate(expression, 0, &mut StdRng::seed_from_u64(1)),
+            Err(RollError::NoSamples)
+        );
+    }
+}
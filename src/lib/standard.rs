@@ -1,118 +1,690 @@
 use super::*;
+use pest::error::{ErrorVariant, InputLocation};
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::Parser;
 use pest_derive::Parser;
-use std::io::ErrorKind;
+use std::str::FromStr;
+use std::sync::OnceLock;
 
 #[derive(Parser)]
 #[grammar = "lib/standard.pest"]
 pub struct StandardNotation;
 
-impl RollExpression {
-    pub fn from_pairs<'i>(mut value: pest::iterators::Pairs<'i, Rule>) -> Vec<RollExpression> {
-        let Some(rolls) = value.nth(0) else {
-            panic!("no matched patterns!");
+fn pratt_parser() -> &'static PrattParser<Rule> {
+    static PRATT_PARSER: OnceLock<PrattParser<Rule>> = OnceLock::new();
+    PRATT_PARSER.get_or_init(|| {
+        PrattParser::new()
+            .op(Op::infix(Rule::OperatorAdd, Assoc::Left)
+                | Op::infix(Rule::OperatorSubtract, Assoc::Left))
+            .op(Op::infix(Rule::OperatorMultiply, Assoc::Left)
+                | Op::infix(Rule::OperatorDivide, Assoc::Left))
+            .op(Op::prefix(Rule::OperatorNegate))
+    })
+}
+
+type PestError = Box<pest::error::Error<Rule>>;
+
+fn invalid(pair: &Pair<'_, Rule>, message: &str) -> PestError {
+    Box::new(pest::error::Error::new_from_span(
+        ErrorVariant::CustomError {
+            message: message.to_string(),
+        },
+        pair.as_span(),
+    ))
+}
+
+/// Applies a shorthand such as ` adv` that follows `expression`.
+fn shorthand(
+    expression: RollExpression,
+    pair: &Pair<'_, Rule>,
+) -> Result<RollExpression, PestError> {
+    let expression = match pair.as_rule() {
+        Rule::Advantage => dnd::advantage(&expression),
+        Rule::Disadvantage => dnd::disadvantage(&expression),
+        _ => return Err(invalid(pair, "unexpected shorthand")),
+    };
+    expression.ok_or_else(|| invalid(pair, "expected a d20 to roll twice"))
+}
+
+/// Parses `input` as `rule`, refusing brackets nested deeper than [`MAX_NESTING`] before
+/// the parser recurses into them.
+fn parse_rule(rule: Rule, input: &str) -> Result<Pairs<'_, Rule>, ParseError> {
+    let mut depth = 0usize;
+    for (i, c) in input.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth = depth.saturating_sub(1),
+            _ => continue,
+        }
+        if depth > MAX_NESTING {
+            return Err(ParseError {
+                input: input.to_string(),
+                span: i..i + 1,
+                expected: Vec::new(),
+                message: format!("brackets may only nest {} deep", MAX_NESTING),
+            });
+        }
+    }
+    StandardNotation::parse(rule, input).map_err(|e| ParseError::from_pest(input, e))
+}
+
+fn parse_number<T: FromStr>(pair: &Pair<'_, Rule>) -> Result<T, PestError> {
+    pair.as_str()
+        .trim()
+        .parse()
+        .map_err(|_| invalid(pair, "number too large"))
+}
+
+impl Repeat {
+    pub fn from_pairs(mut value: Pairs<'_, Rule>) -> Result<Vec<Repeat>, PestError> {
+        let Some(rolls) = value.next() else {
+            return Err(Box::new(pest::error::Error::new_from_pos(
+                ErrorVariant::CustomError {
+                    message: "no matched patterns".to_string(),
+                },
+                pest::Position::from_start(""),
+            )));
         };
 
         if rolls.as_rule() != Rule::Rolls {
-            panic!("not root");
+            return Err(invalid(&rolls, "expected a list of rolls"));
         };
 
-        let expressions: Vec<RollExpression> = rolls
+        rolls
             .into_inner()
-            .filter_map(|r| {
-                if r.as_rule() == Rule::EOI {
-                    None
-                } else {
-                    Some(r.into())
-                }
-            })
-            .collect();
-        expressions
+            .filter(|r| r.as_rule() != Rule::EOI)
+            .map(Repeat::try_from)
+            .collect()
+    }
+}
+
+impl RollExpression {
+    /// Converts parsed rolls into expressions, with one per repetition of a repeat.
+    pub fn from_pairs(value: Pairs<'_, Rule>) -> Result<Vec<RollExpression>, PestError> {
+        Ok(Repeat::from_pairs(value)?
+            .into_iter()
+            .flat_map(|r| vec![r.expression; r.count])
+            .collect())
     }
 }
 
 impl Notation for StandardNotation {
-    fn parse_from_str(input: &str) -> Result<Vec<RollExpression>, std::io::Error> {
-        let pairs =
-            StandardNotation::parse(Rule::Rolls, input).map_err(|_| ErrorKind::InvalidData)?;
-        Ok(RollExpression::from_pairs(pairs))
+    fn parse_repeats(input: &str) -> Result<Vec<Repeat>, Error> {
+        let pairs = parse_rule(Rule::Rolls, input)?;
+        let repeats = Repeat::from_pairs(pairs).map_err(|e| ParseError::from_pest(input, *e))?;
+        for repeat in repeats.iter() {
+            repeat.validate()?;
+        }
+        Ok(repeats)
     }
 }
 
-impl<'i> From<pest::iterators::Pair<'i, Rule>> for RollExpression {
-    fn from(value: pest::iterators::Pair<'i, Rule>) -> Self {
-        if value.as_rule() != Rule::RollExpression {
-            panic!("expected a roll expression")
-        };
-        let roll_expression = value
-            .into_inner()
-            .collect::<Vec<pest::iterators::Pair<'i, Rule>>>();
-        let dice = roll_expression.get(0).expect("no die expression");
-        if dice.as_rule() != Rule::Dice {
-            panic!("expected a die expression")
-        };
+impl FromStr for RollExpression {
+    type Err = Error;
 
-        let mut inner = dice.clone().into_inner();
-        let mut count: usize = 1;
-        let mut faces: usize = 6;
+    /// Parses a single expression in standard notation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pairs = parse_rule(Rule::Roll, s)?;
+        let mut inner = pairs.next().map(|roll| roll.into_inner());
+        let expression = inner.as_mut().and_then(|roll| roll.next()).ok_or_else(|| {
+            ParseError::from_pest(
+                s,
+                pest::error::Error::new_from_pos(
+                    ErrorVariant::CustomError {
+                        message: "expected a roll expression".to_string(),
+                    },
+                    pest::Position::from_start(s),
+                ),
+            )
+        })?;
+        let mut expression =
+            RollExpression::try_from(expression).map_err(|e| ParseError::from_pest(s, *e))?;
+        if let Some(pair) = inner.and_then(|mut roll| roll.find(|p| p.as_rule() != Rule::EOI)) {
+            expression = shorthand(expression, &pair).map_err(|e| ParseError::from_pest(s, *e))?;
+        }
+        expression.validate()?;
+        Ok(expression)
+    }
+}
 
-        let mut t = inner.next().unwrap();
-        if t.as_rule() == Rule::DiceCount {
-            count = t.as_str().parse().unwrap();
-            t = inner.next().unwrap();
+impl Rule {
+    fn describe(&self) -> &'static str {
+        match self {
+            Rule::NaturalNumber | Rule::Integer | Rule::DiceCount | Rule::DiceSize => "number",
+            Rule::SignedInteger => "number",
+            Rule::DiceType | Rule::DicePercent | Rule::DiceFudge => "die size",
+            Rule::Face | Rule::FaceSymbol | Rule::FaceList | Rule::FaceRange => "die faces",
+            Rule::Dice | Rule::ConditionDice => "dice",
+            Rule::Grouped | Rule::ConditionGrouped => "roll expression",
+            Rule::RollGroup => "roll group",
+            Rule::RepeatCount => "number",
+            Rule::Variable | Rule::VariableName => "variable",
+            Rule::Function => "function",
+            Rule::NestedCount | Rule::NestedSides => "rolled dice",
+            Rule::If => "conditional",
+            Rule::WodCount | Rule::WodAgain => "number",
+            Rule::WodRote => "`r`",
+            Rule::WodPool => "dice pool",
+            Rule::ShadowrunCount | Rule::ShadowrunLimit => "number",
+            Rule::ShadowrunEdge => "`!`",
+            Rule::ShadowrunPool => "dice pool",
+            Rule::Advantage => "`adv`",
+            Rule::Disadvantage => "`dis`",
+            Rule::Condition | Rule::ConditionOperand => "condition",
+            Rule::ComparisonLess => "`<`",
+            Rule::ComparisonAtMost => "`<=`",
+            Rule::ComparisonEqual => "`==`",
+            Rule::ComparisonNotEqual => "`!=`",
+            Rule::ComparisonAtLeast => "`>=`",
+            Rule::ComparisonGreater => "`>`",
+            Rule::FunctionFloor => "`floor`",
+            Rule::FunctionCeil => "`ceil`",
+            Rule::FunctionRound => "`round`",
+            Rule::FunctionAbs => "`abs`",
+            Rule::FunctionMin => "`min`",
+            Rule::FunctionMax => "`max`",
+            Rule::Repeat => "roll expression",
+            Rule::Modifier
+            | Rule::ModifierExplode
+            | Rule::ExplodeCompound
+            | Rule::ExplodePenetrate
+            | Rule::ExplodeLimit
+            | Rule::ModifierReroll
+            | Rule::ModifierRerollOnce
+            | Rule::ModifierSuccess
+            | Rule::ModifierFailure
+            | Rule::ModifierDouble
+            | Rule::ModifierCritical
+            | Rule::ModifierFumble
+            | Rule::ConditionModifier
+            | Rule::ConditionExplode => "modifier",
+            Rule::ComparePoint
+            | Rule::CompareEqual
+            | Rule::CompareAtMost
+            | Rule::CompareAtLeast => "comparison",
+            Rule::Retention
+            | Rule::RetentionHighest
+            | Rule::RetentionLowest
+            | Rule::RetentionMiddle
+            | Rule::DropHighest
+            | Rule::DropLowest => "retention",
+            Rule::OperatorAdd => "`+`",
+            Rule::OperatorSubtract | Rule::OperatorNegate => "`-`",
+            Rule::OperatorMultiply => "`x`",
+            Rule::OperatorDivide => "`/`",
+            Rule::RollExpression | Rule::Roll | Rule::Rolls => "roll expression",
+            Rule::EOI => "end of input",
+            _ => "token",
         }
+    }
+}
+
+impl ParseError {
+    fn from_pest(input: &str, error: pest::error::Error<Rule>) -> Self {
+        let span = match error.location {
+            InputLocation::Pos(p) => p..p,
+            InputLocation::Span((start, end)) => start..end,
+        };
 
-        if t.as_rule() == Rule::DiceType {
-            match t.as_str() {
-                "%" => faces = 100,
-                n => faces = n.parse().unwrap(),
+        let (expected, message) = match error.variant {
+            ErrorVariant::ParsingError { positives, .. } => {
+                let mut expected: Vec<String> = Vec::new();
+                for rule in positives.iter() {
+                    let name = rule.describe().to_string();
+                    if !expected.contains(&name) {
+                        expected.push(name);
+                    }
+                }
+                let message = match expected.as_slice() {
+                    [] => "unexpected input".to_string(),
+                    [only] => format!("expected {}", only),
+                    [init @ .., last] => format!("expected {} or {}", init.join(", "), last),
+                };
+                (expected, message)
             }
+            ErrorVariant::CustomError { message } => (Vec::new(), message),
+        };
+
+        ParseError {
+            input: input.to_string(),
+            span,
+            expected,
+            message,
         }
+    }
+}
 
-        let retention = roll_expression
-            .iter()
-            .find_map(|r| {
-                if r.as_rule() == Rule::Retention {
-                    let r = r.clone().into_inner().nth(0).unwrap();
-                    match r.as_rule() {
-                        Rule::RetentionHighest => Some(RollRetention::Highest(
-                            r.into_inner().as_str().parse().unwrap(),
-                        )),
-                        Rule::RetentionLowest => Some(RollRetention::Lowest(
-                            r.into_inner().as_str().parse().unwrap(),
-                        )),
-                        _ => None,
+impl<'i> TryFrom<Pair<'i, Rule>> for RollExpression {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if !matches!(
+            value.as_rule(),
+            Rule::RollExpression | Rule::ConditionOperand
+        ) {
+            return Err(invalid(&value, "expected a roll expression"));
+        };
+
+        pratt_parser()
+            .map_primary(|primary| match primary.as_rule() {
+                Rule::Integer => Ok(RollExpression::Constant(parse_number(&primary)?)),
+                Rule::Dice | Rule::ConditionDice
+                    if primary
+                        .clone()
+                        .into_inner()
+                        .any(|p| matches!(p.as_rule(), Rule::NestedCount | Rule::NestedSides)) =>
+                {
+                    Ok(RollExpression::Nested(primary.try_into()?))
+                }
+                Rule::Dice | Rule::ConditionDice => Ok(RollExpression::Dice(primary.try_into()?)),
+                Rule::Grouped | Rule::ConditionGrouped => {
+                    let mut inner = primary.clone().into_inner();
+                    match (inner.next(), inner.next()) {
+                        (Some(count), None) => match count.into_inner().next() {
+                            Some(e) => e.try_into(),
+                            None => Err(invalid(&primary, "expected a roll expression")),
+                        },
+                        _ => Ok(RollExpression::Nested(primary.try_into()?)),
                     }
-                } else {
-                    None
                 }
+                Rule::RollGroup => Ok(RollExpression::Group(primary.try_into()?)),
+                Rule::Variable => match primary.clone().into_inner().next() {
+                    Some(name) => Ok(RollExpression::Variable(name.as_str().to_string())),
+                    None => Err(invalid(&primary, "expected a variable name")),
+                },
+                Rule::Function => {
+                    let mut inner = primary.clone().into_inner();
+                    let function = match inner.next().map(|f| f.as_rule()) {
+                        Some(Rule::FunctionFloor) => Function::Floor,
+                        Some(Rule::FunctionCeil) => Function::Ceil,
+                        Some(Rule::FunctionRound) => Function::Round,
+                        Some(Rule::FunctionAbs) => Function::Abs,
+                        Some(Rule::FunctionMin) => Function::Min,
+                        Some(Rule::FunctionMax) => Function::Max,
+                        _ => return Err(invalid(&primary, "unknown function")),
+                    };
+                    let args = inner.map(|a| a.try_into()).collect::<Result<_, _>>()?;
+                    Ok(RollExpression::Function(function, args))
+                }
+                Rule::If => Ok(RollExpression::If(primary.try_into()?)),
+                _ => Err(invalid(&primary, "unexpected term")),
+            })
+            .map_prefix(|op, rhs| match op.as_rule() {
+                Rule::OperatorNegate => Ok(RollExpression::Negate(Box::new(rhs?))),
+                _ => Err(invalid(&op, "unexpected prefix operator")),
+            })
+            .map_infix(|lhs, op, rhs| {
+                let op = match op.as_rule() {
+                    Rule::OperatorAdd => Operator::Add,
+                    Rule::OperatorSubtract => Operator::Subtract,
+                    Rule::OperatorMultiply => Operator::Multiply,
+                    Rule::OperatorDivide => Operator::Divide,
+                    _ => return Err(invalid(&op, "unexpected infix operator")),
+                };
+                Ok(RollExpression::Binary(op, Box::new(lhs?), Box::new(rhs?)))
             })
-            .unwrap_or(RollRetention::All);
+            .parse(value.into_inner())
+    }
+}
 
-        let modifiers = roll_expression
-            .iter()
-            .filter_map(|r| match r.as_rule() {
-                Rule::Modifier => {
-                    let modifier = r.clone().into_inner().nth(0).unwrap();
-                    let n = modifier.clone().into_inner().as_str().trim().parse();
-                    match modifier.as_rule() {
-                        Rule::ModifierAdd => Some(RollModifier::Add(n.unwrap())),
-                        Rule::ModifierSubtract => Some(RollModifier::Subtract(n.unwrap())),
-                        Rule::ModifierMultiply => Some(RollModifier::Multiply(n.unwrap())),
-                        Rule::ModifierDivide => Some(RollModifier::Divide(n.unwrap())),
-                        Rule::ModifierExplode => Some(RollModifier::Explode(n.unwrap_or(faces))),
-                        _ => None,
+impl<'i> TryFrom<Pair<'i, Rule>> for Dice {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if !matches!(
+            value.as_rule(),
+            Rule::Dice | Rule::ConditionDice | Rule::Grouped | Rule::ConditionGrouped
+        ) {
+            return Err(invalid(&value, "expected a die expression"));
+        };
+
+        let mut count: usize = 1;
+        let mut faces = Faces::Standard(6);
+        let mut retention = RollRetention::All;
+        let mut modifiers: Vec<RollModifier> = Vec::new();
+
+        for t in value.into_inner() {
+            match t.as_rule() {
+                Rule::DiceCount => count = parse_number(&t)?,
+                Rule::DiceType => faces = t.try_into()?,
+                // Rolled sides are only known later, so a plain `!` explodes on `>0`
+                // until they are. See `NestedDice::resolve`.
+                Rule::NestedSides => faces = Faces::Standard(0),
+                Rule::Retention => {
+                    if retention != RollRetention::All {
+                        return Err(invalid(&t, "dice may only have one retention"));
                     }
+                    retention = t.try_into()?;
                 }
-                _ => None,
-            })
-            .collect::<Vec<RollModifier>>();
+                Rule::Modifier | Rule::ConditionModifier => {
+                    let Some(m) = t.into_inner().next() else {
+                        continue;
+                    };
+                    let n = m.clone().into_inner().next();
+                    match (m.as_rule(), n) {
+                        (Rule::ModifierExplode | Rule::ConditionExplode, _) => {
+                            let mut kind = Explosion::Standard;
+                            let mut compare = ComparePoint::AtLeast(faces.max());
+                            let mut limit = None;
+                            for e in m.into_inner() {
+                                match e.as_rule() {
+                                    Rule::ExplodeCompound => kind = Explosion::Compound,
+                                    Rule::ExplodePenetrate => kind = Explosion::Penetrate,
+                                    Rule::NaturalNumber => {
+                                        compare = ComparePoint::AtLeast(parse_number(&e)?)
+                                    }
+                                    Rule::ComparePoint => compare = e.try_into()?,
+                                    Rule::ExplodeLimit => {
+                                        if let Some(n) = e.into_inner().next() {
+                                            limit = Some(parse_number(&n)?);
+                                        }
+                                    }
+                                    _ => return Err(invalid(&e, "unexpected explosion")),
+                                }
+                            }
+                            modifiers.push(RollModifier::Explode {
+                                compare,
+                                kind,
+                                limit,
+                            })
+                        }
+                        (Rule::ModifierReroll, Some(n)) => modifiers.push(RollModifier::Reroll {
+                            compare: n.try_into()?,
+                            once: false,
+                        }),
+                        (Rule::ModifierRerollOnce, Some(n)) => {
+                            modifiers.push(RollModifier::Reroll {
+                                compare: n.try_into()?,
+                                once: true,
+                            })
+                        }
+                        (Rule::ModifierSuccess, Some(n)) => {
+                            modifiers.push(RollModifier::Success(n.try_into()?))
+                        }
+                        (Rule::ModifierFailure, Some(n)) => {
+                            modifiers.push(RollModifier::Failure(n.try_into()?))
+                        }
+                        (Rule::ModifierDouble, Some(n)) => {
+                            modifiers.push(RollModifier::DoubleSuccess(n.try_into()?))
+                        }
+                        (Rule::ModifierCritical, Some(n)) => {
+                            modifiers.push(RollModifier::CriticalSuccess(n.try_into()?))
+                        }
+                        (Rule::ModifierFumble, Some(n)) => {
+                            modifiers.push(RollModifier::CriticalFailure(n.try_into()?))
+                        }
+                        _ => return Err(invalid(&m, "unexpected modifier")),
+                    }
+                }
+                _ => {}
+            }
+        }
 
-        RollExpression {
+        Ok(Dice {
             faces,
             count,
             retention,
             modifiers,
+        })
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for NestedDice {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if !matches!(
+            value.as_rule(),
+            Rule::Dice | Rule::ConditionDice | Rule::Grouped | Rule::ConditionGrouped
+        ) {
+            return Err(invalid(&value, "expected a die expression"));
+        };
+
+        let mut count = None;
+        let mut sides = None;
+
+        for t in value.clone().into_inner() {
+            let expression = || match t.clone().into_inner().next() {
+                Some(e) => Ok(Box::new(RollExpression::try_from(e)?)),
+                None => Err(invalid(&t, "expected a roll expression")),
+            };
+            match t.as_rule() {
+                Rule::NestedCount => count = Some(expression()?),
+                Rule::NestedSides => sides = Some(expression()?),
+                _ => {}
+            }
+        }
+
+        Ok(NestedDice {
+            count,
+            sides,
+            dice: value.try_into()?,
+        })
+    }
+}
+
+/// Parses a World of Darkness dice pool, such as `7wod8`.
+impl FromStr for wod::Pool {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = parse_rule(Rule::WodPool, s)?;
+
+        let mut pool = wod::Pool::default();
+        for t in pairs.flatten() {
+            match t.as_rule() {
+                Rule::WodCount => {
+                    pool.dice = parse_number(&t).map_err(|e| ParseError::from_pest(s, *e))?
+                }
+                Rule::WodAgain => {
+                    pool.again = parse_number(&t).map_err(|e| ParseError::from_pest(s, *e))?
+                }
+                Rule::WodRote => pool.rote = true,
+                _ => {}
+            }
+        }
+        pool.validate()?;
+        Ok(pool)
+    }
+}
+
+/// Parses a Shadowrun dice pool, such as `12sr4`.
+impl FromStr for shadowrun::Pool {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pairs = parse_rule(Rule::ShadowrunPool, s)?;
+
+        let mut pool = shadowrun::Pool::default();
+        for t in pairs.flatten() {
+            match t.as_rule() {
+                Rule::ShadowrunCount => {
+                    pool.dice = parse_number(&t).map_err(|e| ParseError::from_pest(s, *e))?
+                }
+                Rule::ShadowrunLimit => {
+                    pool.limit = Some(parse_number(&t).map_err(|e| ParseError::from_pest(s, *e))?)
+                }
+                Rule::ShadowrunEdge => pool.edge = true,
+                _ => {}
+            }
+        }
+        pool.validate()?;
+        Ok(pool)
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for Conditional {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if value.as_rule() != Rule::If {
+            return Err(invalid(&value, "expected a conditional"));
+        };
+
+        let mut inner = value.clone().into_inner();
+        let (Some(condition), Some(then), Some(otherwise)) =
+            (inner.next(), inner.next(), inner.next())
+        else {
+            return Err(invalid(&value, "expected a condition and two branches"));
+        };
+
+        let mut terms = condition.clone().into_inner();
+        let Some(lhs) = terms.next() else {
+            return Err(invalid(&condition, "expected a condition"));
+        };
+        let comparison = match (terms.next(), terms.next()) {
+            (Some(op), Some(rhs)) => {
+                let comparison = match op.as_rule() {
+                    Rule::ComparisonLess => Comparison::Less,
+                    Rule::ComparisonAtMost => Comparison::AtMost,
+                    Rule::ComparisonEqual => Comparison::Equal,
+                    Rule::ComparisonNotEqual => Comparison::NotEqual,
+                    Rule::ComparisonAtLeast => Comparison::AtLeast,
+                    Rule::ComparisonGreater => Comparison::Greater,
+                    _ => return Err(invalid(&op, "unexpected comparison")),
+                };
+                Some((comparison, Box::new(rhs.try_into()?)))
+            }
+            _ => None,
+        };
+
+        Ok(Conditional {
+            condition: Box::new(lhs.try_into()?),
+            comparison,
+            then: Box::new(then.try_into()?),
+            otherwise: Box::new(otherwise.try_into()?),
+        })
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for Repeat {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if value.as_rule() != Rule::Repeat {
+            return Err(invalid(&value, "expected a roll"));
+        };
+
+        let mut count: usize = 1;
+        let mut expression = None;
+
+        for t in value.clone().into_inner() {
+            match t.as_rule() {
+                Rule::RepeatCount => count = parse_number(&t)?,
+                Rule::RollExpression => expression = Some(t.try_into()?),
+                Rule::Advantage | Rule::Disadvantage => {
+                    expression = match expression {
+                        Some(e) => Some(shorthand(e, &t)?),
+                        None => return Err(invalid(&t, "expected a roll expression")),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match expression {
+            Some(expression) => Ok(Repeat { count, expression }),
+            None => Err(invalid(&value, "expected a roll expression")),
+        }
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for Group {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if value.as_rule() != Rule::RollGroup {
+            return Err(invalid(&value, "expected a roll group"));
+        };
+
+        let mut members: Vec<RollExpression> = Vec::new();
+        let mut retention = RollRetention::All;
+
+        for t in value.into_inner() {
+            match t.as_rule() {
+                Rule::RollExpression => members.push(t.try_into()?),
+                Rule::Retention => retention = t.try_into()?,
+                _ => {}
+            }
+        }
+
+        Ok(Group { members, retention })
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for RollRetention {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        let Some(r) = value.clone().into_inner().next() else {
+            return Err(invalid(&value, "expected a retention"));
+        };
+        let n = r.clone().into_inner().next().map(|n| parse_number(&n));
+        match (r.as_rule(), n) {
+            (Rule::RetentionHighest, Some(n)) => Ok(RollRetention::Highest(n?)),
+            (Rule::RetentionLowest, Some(n)) => Ok(RollRetention::Lowest(n?)),
+            (Rule::RetentionMiddle, Some(n)) => Ok(RollRetention::Middle(n?)),
+            (Rule::DropHighest, Some(n)) => Ok(RollRetention::DropHighest(n?)),
+            (Rule::DropLowest, Some(n)) => Ok(RollRetention::DropLowest(n?)),
+            _ => Err(invalid(&r, "unexpected retention")),
+        }
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for ComparePoint {
+    type Error = PestError;
+
+    /// Reads a comparison point, where a bare number means the faces equal to it.
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        if value.as_rule() == Rule::SignedInteger {
+            return Ok(ComparePoint::Equal(parse_number(&value)?));
+        }
+
+        let compare = value.clone().into_inner().next();
+        let n = compare.clone().and_then(|c| c.into_inner().next());
+        match (compare.map(|c| c.as_rule()), n) {
+            (Some(Rule::CompareEqual), Some(n)) => Ok(ComparePoint::Equal(parse_number(&n)?)),
+            (Some(Rule::CompareAtMost), Some(n)) => Ok(ComparePoint::AtMost(parse_number(&n)?)),
+            (Some(Rule::CompareAtLeast), Some(n)) => Ok(ComparePoint::AtLeast(parse_number(&n)?)),
+            _ => Err(invalid(&value, "expected a comparison")),
+        }
+    }
+}
+
+impl<'i> TryFrom<Pair<'i, Rule>> for Faces {
+    type Error = PestError;
+
+    fn try_from(value: Pair<'i, Rule>) -> Result<Self, Self::Error> {
+        let Some(t) = value.clone().into_inner().next() else {
+            return Err(invalid(&value, "expected a die size"));
+        };
+
+        match t.as_rule() {
+            Rule::DiceSize => Ok(Faces::Standard(parse_number(&t)?)),
+            Rule::DicePercent => Ok(Faces::Standard(100)),
+            Rule::DiceFudge => Ok(Faces::Fudge),
+            Rule::FaceList => t
+                .into_inner()
+                .map(|face| match face.into_inner().next() {
+                    Some(n) if n.as_rule() == Rule::SignedInteger => {
+                        Ok(Face::Number(parse_number(&n)?))
+                    }
+                    Some(s) => Ok(Face::Symbol(s.as_str().to_string())),
+                    None => Err(invalid(&value, "expected a face")),
+                })
+                .collect::<Result<Vec<Face>, PestError>>()
+                .map(Faces::Custom),
+            Rule::FaceRange => {
+                let mut bounds = t.clone().into_inner();
+                match (bounds.next(), bounds.next()) {
+                    (Some(start), Some(end)) => {
+                        Ok(Faces::Range(parse_number(&start)?, parse_number(&end)?))
+                    }
+                    _ => Err(invalid(&t, "expected a range of faces")),
+                }
+            }
+            _ => Err(invalid(&t, "unexpected die size")),
         }
     }
 }
@@ -121,12 +693,108 @@ impl<'i> From<pest::iterators::Pair<'i, Rule>> for RollExpression {
 mod test {
     use super::*;
     use pest::Parser;
+    use rand::{rngs::StdRng, SeedableRng};
 
-    const LEGAL_ROLLS: &'static [&'static str] = &[
-        "d20", "1d20", "3d10+3", "10d6 - 5", "d6x4", "8d8 / 2", "d%", "12d%",
+    const LEGAL_ROLLS: &[&str] = &[
+        "d20",
+        "1d20",
+        "3d10+3",
+        "10d6 - 5",
+        "d6x4",
+        "8d8 / 2",
+        "d%",
+        "12d%",
+        "-2d6",
+        "2/1d8",
+        "2d6 + 1d4 + 3",
+        "(1d8+2)*2",
+        "1d8+1d6+4",
+        "-(2d4 - 1)",
+        "4dF",
+        "4df+2",
+        "d{1,1,2,3,5,8}",
+        "3d{-1, 0, 1}",
+        "2d[0..9]+1",
+        "d[-3..3]",
+        "4d{hit,hit,miss,crit}",
+        "3d{1,2,3}h1",
+        "4d6dl1",
+        "4d6kh3",
+        "2d20kl1",
+        "3d20km1",
+        "5d10 DH2",
+        "2d6ro<2",
+        "1d20r1",
+        "4d6r1kh3",
+        "4dFr=-1",
+        "3d10r>9ro<2",
+        "5d6!!",
+        "3d10!p",
+        "2d6!>4",
+        "d6!<2",
+        "d6!=6",
+        "d8!!>7:5",
+        "4dF!p=1",
+        "10d10>=8",
+        "10d10>8f1",
+        "6d10 >7 ds10 f<1",
+        "4dF=1",
+        "5d6<=2",
+        "1d20cs>=19",
+        "1d100cf>=96cs<=5",
+        "1d20 cs19 cs20 cf1",
+        "{4d6, 3d8}kh1",
+        "{1d20+5,1d20+5}kh1",
+        "{ 2d6 , 1d4 } dl1 + 2",
+        "-{1d4}*{3, 1d6}l1",
+        "1d20+@str+@prof",
+        "{1d20 + @saves.dex}kh1",
+        "-@_penalty",
+        "ceil(3d6/2)",
+        "max(1, 1d4-2)",
+        "ABS(-1d6) + min( 1d4 , 1d6 , 2 )",
+        "(1d4)d6",
+        "2d(1d4*2)",
+        "( 1d3+1 )d( 2d4 )kh2!",
+        "if(1d20+5>=15, 2d6, 1d6)",
+        "IF(1d20>=15,2d6,1d6)",
+        "if( @hp < 10 , 0 , if(1d4 != 1, 1d8, 2d8) )",
+        "1d20+5 adv",
+        "1d20+1d4 DIS",
     ];
-    const ILLEGAL_ROLLS: &'static [&'static str] = &[
-        "d0", "0d6", "3d10 3+", "%d", "-2d6", "2/1d8", "d%20", "%d10",
+    const ILLEGAL_ROLLS: &[&str] = &[
+        "d0",
+        "0d6",
+        "3d10 3+",
+        "%d",
+        "d%20",
+        "%d10",
+        "(1d6",
+        "2d6 +",
+        "1d4)",
+        "d{}",
+        "d{1,}",
+        "d[1..]",
+        "d{_a}",
+        "4d6d1",
+        "4d6kx1",
+        "1d6r",
+        "1d6ro",
+        "1d6r<",
+        "#4d6",
+        "0#4d6",
+        "6#",
+        "repeat(",
+        "@",
+        "@1st",
+        "@a.",
+        "@a.1",
+        "ceil()",
+        "max(1,)",
+        "sqrt(4)",
+        "if(1d6,1)",
+        "if(1d6>, 1, 2)",
+        "if(1d6, 1, 2, 3)",
     ];
 
     #[test]
@@ -141,7 +809,864 @@ mod test {
     pub fn fails_all_bad_examples() {
         for input in ILLEGAL_ROLLS {
             let res = StandardNotation::parse(Rule::Rolls, input);
-            assert!(res.is_err());
+            assert!(res.is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    pub fn reports_error_spans() {
+        let Err(Error::Parse(e)) = StandardNotation::parse_from_str("3d10 3+") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(e.span, 7..7);
+        assert!(e.expected.contains(&"number".to_string()));
+        assert_eq!(e.render(), format!("3d10 3+\n       ^ {}", e.message),);
+
+        let Err(Error::Parse(e)) = StandardNotation::parse_from_str("2d6 adv") else {
+            panic!("expected a parse error");
+        };
+        assert_eq!(e.span, 4..7);
+        assert_eq!(e.message, "expected a d20 to roll twice");
+    }
+
+    #[test]
+    pub fn seeded_rolls_are_reproducible() {
+        let rolls = StandardNotation::parse_from_str("4d6h3+1d8! 10d100l2").unwrap();
+        for r in rolls.iter() {
+            let first = r.roll_with(&mut StdRng::seed_from_u64(20));
+            let second = r.roll_with(&mut StdRng::seed_from_u64(20));
+            assert_eq!(first, second);
+        }
+
+        let rolls = StandardNotation::parse_from_str("3d1+2").unwrap();
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(0));
+        assert_eq!(
+            result,
+            RollResult {
+                input: "3d1+2".to_string(),
+                total: 5,
+                groups: vec![],
+                nested: vec![],
+                conditions: vec![],
+                terms: vec![TermResult {
+                    input: "3d1".to_string(),
+                    total: 3,
+                    faces: Faces::Standard(1),
+                    successes: None,
+                    rolls: vec![
+                        RollItem {
+                            value: 1,
+                            retained: true,
+                            quality: RollQuality::Good,
+                            successes: None,
+                            critical: None,
+                            symbol: None,
+                            reason: None,
+                        };
+                        3
+                    ],
+                }],
+            }
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn serializes_expressions_and_results() {
+        let rolls = StandardNotation::parse_from_str("(4d6h3+1d4!)x2").unwrap();
+        let json = serde_json::to_string(&rolls[0]).unwrap();
+        let expression: RollExpression = serde_json::from_str(&json).unwrap();
+
+        let result = expression.roll_with(&mut StdRng::seed_from_u64(8));
+        assert_eq!(result, rolls[0].roll_with(&mut StdRng::seed_from_u64(8)));
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["input"], "(4d6h3+1d4!)x2");
+        assert_eq!(json["total"], result.total);
+        assert_eq!(json["terms"][0]["rolls"].as_array().unwrap().len(), 4);
+        assert!(json["terms"][0]["rolls"][0]["retained"].is_boolean());
+        assert!(json["terms"][0]["rolls"][0]["quality"].is_string());
+    }
+
+    #[test]
+    pub fn displays_canonical_notation() {
+        for (input, canonical) in [
+            ("d20", "1d20"),
+            ("2d6 + 1d4 + 3", "2d6+1d4+3"),
+            ("(1d8+2)*2", "(1d8+2)x2"),
+            ("d6!6", "1d6!"),
+            ("4D6 k3", "4d6h3"),
+            ("12d%l2", "12d100l2"),
+            ("10 - (2 - 1d4)", "10-(2-1d4)"),
+            ("(10 - 2) - 1d4", "10-2-1d4"),
+            ("2 - -1", "2-(-1)"),
+            ("--(1d6/2)", "-(-(1d6/2))"),
+            ("dF!", "1dF!"),
+            ("d{1, 1, 2}", "1d{1,1,2}"),
+            ("2d{ -1,0 ,1 }!", "2d{-1,0,1}!"),
+            ("d[ 0 .. 9 ]", "1d[0..9]"),
+            ("d{hit,miss}", "1d{hit,miss}"),
+            ("4d6kh3", "4d6h3"),
+            ("2d20KL1", "2d20l1"),
+            ("3d20 km1", "3d20km1"),
+            ("4d6dl1", "4d6dl1"),
+            ("3d8dh 2", "3d8dh2"),
+            ("2d6ro<2", "2d6ro<2"),
+            ("d20R=1", "1d20r1"),
+            ("4d6r1kh3", "4d6h3r1"),
+            ("dFr<-1", "1dFr<-1"),
+            ("d6!>6", "1d6!"),
+            ("d6!>4", "1d6!4"),
+            ("5d6!!", "5d6!!"),
+            ("3d10!P>9", "3d10!p9"),
+            ("d6!=6", "1d6!=6"),
+            ("d6!<2:3", "1d6!<2:3"),
+            ("dF!<-1", "1dF!<-1"),
+            ("dF!>0", "1dF!>0"),
+            ("10d10>=8", "10d10>8"),
+            ("10d10 >8 f1", "10d10>8f1"),
+            ("6d10>=8f<=2ds=10", "6d10>8f<2ds10"),
+            ("5d6<=2", "5d6<2"),
+            ("d20cs>=19", "1d20cs>19"),
+            ("d100 cf>96 CS<5", "1d100cf>96cs<5"),
+            ("d20cs=20cf1", "1d20cs20cf1"),
+            ("{4d6, 3d8}kh1", "{4d6,3d8}h1"),
+            ("{ 1d20 + 5 , 1d20+5 } KL1", "{1d20+5,1d20+5}l1"),
+            ("2*{1d6}", "2x{1d6}"),
+            ("CEIL( 3d6 / 2 )", "ceil(3d6/2)"),
+            ("max(1, 1d4 - 2)", "max(1,1d4-2)"),
+            ("-abs(1d6)", "-abs(1d6)"),
+            ("(1d4)D6", "(1d4)d6"),
+            ("2d( 1d4 * 2 )!", "2d(1d4x2)!"),
+            ("(1d3)d(1d4)kh1!>3", "(1d3)d(1d4)h1!3"),
+            ("if(1d20+5 >= 15, 2d6, 1d6)", "if(1d20+5>=15,2d6,1d6)"),
+            ("if(1d20 >=15, 2d6, 1d6)", "if(1d20>=15,2d6,1d6)"),
+            ("if(1d4 != 1, 1d8, 2d8)", "if(1d4!=1,1d8,2d8)"),
+            ("if(1d6!=6, 1, 0)", "if(1d6!=6,1,0)"),
+            ("if(1d6! > 6, 1, 0)", "if((1d6!)>6,1,0)"),
+            ("if((3d6>5) >= 2, 1, 0)", "if((3d6>5)>=2,1,0)"),
+            ("if((1d20>15), 1, 0)", "if((1d20>15),1,0)"),
+            ("if(1d20cs>19>15, 1, 0)", "if(1d20cs>19>15,1,0)"),
+            ("if(@str = 3, 1, 0)", "if(@str==3,1,0)"),
+            ("if(2 <> 1d4, 1, 0)", "if(2!=1d4,1,0)"),
+            ("1d20+5 adv", "2d20h1+5"),
+            ("1d20 + 1d4  dis", "2d20l1+1d4"),
+        ] {
+            let expression: RollExpression = input.parse().unwrap();
+            assert_eq!(expression.to_string(), canonical);
+        }
+    }
+
+    #[test]
+    pub fn round_trips_through_display() {
+        let mut expressions: Vec<RollExpression> = LEGAL_ROLLS
+            .iter()
+            .map(|input| input.parse().unwrap())
+            .collect();
+        let d6 = RollExpression::Dice(Dice {
+            faces: Faces::Standard(6),
+            count: 3,
+            retention: RollRetention::Lowest(2),
+            modifiers: vec![RollModifier::Explode {
+                compare: ComparePoint::AtLeast(5),
+                kind: Explosion::Penetrate,
+                limit: Some(3),
+            }],
+        });
+        expressions.push(RollExpression::Binary(
+            Operator::Multiply,
+            Box::new(RollExpression::Negate(Box::new(RollExpression::Binary(
+                Operator::Subtract,
+                Box::new(d6.clone()),
+                Box::new(RollExpression::Constant(1)),
+            )))),
+            Box::new(RollExpression::Binary(
+                Operator::Divide,
+                Box::new(RollExpression::Constant(8)),
+                Box::new(RollExpression::Negate(Box::new(d6))),
+            )),
+        ));
+
+        for expression in expressions {
+            let parsed: RollExpression = expression.to_string().parse().unwrap();
+            assert_eq!(parsed, expression);
+        }
+    }
+
+    #[test]
+    pub fn parses_only_single_expressions() {
+        assert!("2d6 1d4".parse::<RollExpression>().is_err());
+        assert!(" 2d6+1 ".parse::<RollExpression>().is_ok());
+    }
+
+    #[test]
+    pub fn rolls_fudge_dice() {
+        colored::control::set_override(false);
+        let rolls = StandardNotation::parse_from_str("4dF").unwrap();
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(4));
+        assert!(result.rolls().all(|r| (-1..=1).contains(&r.value)));
+        assert_eq!(result.total, result.rolls().map(|r| r.value).sum::<isize>());
+
+        let shown = result.to_string();
+        let faces = &shown[shown.find('[').unwrap() + 1..shown.len() - 1];
+        assert!(faces
+            .split(", ")
+            .all(|face| ["+", " ", "-"].contains(&face)));
+    }
+
+    #[test]
+    pub fn drops_and_keeps_dice() {
+        for (input, kept) in [
+            ("6d20dl2", 4),
+            ("6d20dh2", 4),
+            ("6d20km2", 2),
+            ("6d20km1", 1),
+            ("6d20kl5", 5),
+        ] {
+            let rolls = StandardNotation::parse_from_str(input).unwrap();
+            let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(12));
+            let mut values = result.rolls().map(|r| r.value).collect::<Vec<isize>>();
+            values.sort();
+            let mut retained = result
+                .rolls()
+                .filter(|r| r.retained)
+                .map(|r| r.value)
+                .collect::<Vec<isize>>();
+            retained.sort();
+            assert_eq!(retained.len(), kept, "{}", input);
+            assert_eq!(result.total, retained.iter().sum::<isize>(), "{}", input);
+
+            let skip = match input {
+                "6d20dl2" => 2,
+                "6d20km2" | "6d20km1" => (6 - kept) / 2,
+                _ => 0,
+            };
+            assert_eq!(retained, values[skip..skip + kept], "{}", input);
+        }
+    }
+
+    #[test]
+    pub fn rerolls_matching_dice() {
+        let rolls = StandardNotation::parse_from_str("20d6r<2 20d6ro<2 4d6r1dl1").unwrap();
+
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(13));
+        let kept = result.rolls().filter(|r| r.retained).collect::<Vec<_>>();
+        assert_eq!(kept.len(), 20);
+        assert!(kept.iter().all(|r| r.value > 2 && r.reason.is_none()));
+        assert!(result
+            .rolls()
+            .filter(|r| !r.retained)
+            .all(|r| r.value <= 2 && r.reason == Some(DiscardReason::Rerolled)));
+
+        let result = rolls[1].roll_with(&mut StdRng::seed_from_u64(13));
+        let rolls_once = result.rolls().collect::<Vec<_>>();
+        assert_eq!(rolls_once.iter().filter(|r| r.retained).count(), 20);
+        for (i, r) in rolls_once.iter().enumerate() {
+            if !r.retained {
+                assert!(r.value <= 2);
+                // The replacement is kept, whatever it shows.
+                assert!(rolls_once[i + 1].retained);
+            }
+        }
+
+        let result = rolls[2].roll_with(&mut StdRng::seed_from_u64(13));
+        let reasons = result
+            .rolls()
+            .filter_map(|r| r.reason)
+            .collect::<Vec<DiscardReason>>();
+        assert_eq!(
+            reasons
+                .iter()
+                .filter(|r| **r == DiscardReason::Dropped)
+                .count(),
+            1
+        );
+        assert_eq!(result.rolls().filter(|r| r.retained).count(), 3);
+    }
+
+    #[test]
+    pub fn explodes_in_every_style() {
+        let rolls = StandardNotation::parse_from_str("50d6!! 50d6!p 50d6!<2 50d2!:2").unwrap();
+
+        // Compounded dice never stop on the face they exploded on.
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(14));
+        assert_eq!(result.rolls().count(), 50);
+        assert!(result.rolls().any(|r| r.value > 6));
+        assert!(result.rolls().all(|r| r.value % 6 != 0));
+
+        // Every die rolled after a penetrating explosion is one lower.
+        let result = rolls[1].roll_with(&mut StdRng::seed_from_u64(14));
+        let values = result.rolls().map(|r| r.value).collect::<Vec<isize>>();
+        assert!(values.len() > 50);
+        assert!(values.contains(&0));
+        assert!(values.iter().all(|v| (0..=6).contains(v)));
+
+        let result = rolls[2].roll_with(&mut StdRng::seed_from_u64(14));
+        let values = result.rolls().map(|r| r.value).collect::<Vec<isize>>();
+        assert!(values.len() > 50);
+        assert_ne!(values.last(), Some(&1));
+
+        let result = rolls[3].roll_with(&mut StdRng::seed_from_u64(14));
+        assert!(result.rolls().count() <= 150);
+        assert!(result.rolls().count() > 50);
+    }
+
+    #[test]
+    pub fn counts_successes() {
+        colored::control::set_override(false);
+        let rolls = StandardNotation::parse_from_str("12d10>=8f1ds10 3d1>1+2").unwrap();
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(15));
+        let expected = result
+            .rolls()
+            .map(|r| match r.value {
+                10 => 2,
+                8 | 9 => 1,
+                1 => -1,
+                _ => 0,
+            })
+            .sum::<isize>();
+        assert_eq!(result.total, expected);
+        assert_eq!(result.successes(), Some(expected));
+        for r in result.rolls() {
+            let successes = match r.value {
+                10 => 2,
+                8 | 9 => 1,
+                1 => -1,
+                _ => 0,
+            };
+            assert_eq!(r.successes, Some(successes));
+            let quality = match successes {
+                n if n > 0 => RollQuality::Good,
+                n if n < 0 => RollQuality::Bad,
+                _ => RollQuality::Regular,
+            };
+            assert_eq!(r.quality, quality, "quality shows success without cs or cf");
+            let critical = match r.value {
+                10 => RollQuality::Good,
+                1 => RollQuality::Bad,
+                _ => RollQuality::Regular,
+            };
+            assert_eq!(*r.criticality(), critical);
+        }
+        assert!(result
+            .to_string()
+            .ends_with(&format!("({} successes)", expected)));
+
+        let result = rolls[1].roll();
+        assert_eq!(result.total, 5);
+        assert_eq!(result.successes(), Some(3));
+        assert_eq!(
+            StandardNotation::parse_from_str("2d6").unwrap()[0]
+                .roll()
+                .successes(),
+            None
+        );
+    }
+
+    #[test]
+    pub fn flags_critical_rolls() {
+        let rolls = StandardNotation::parse_from_str("40d20cs>19cf<2 40d100cf>96cs<5").unwrap();
+        let mut rng = StdRng::seed_from_u64(16);
+
+        let result = rolls[0].roll_with(&mut rng);
+        for r in result.rolls() {
+            let quality = match r.value {
+                19.. => RollQuality::Good,
+                ..=2 => RollQuality::Bad,
+                _ => RollQuality::Regular,
+            };
+            assert_eq!(r.quality, quality);
+        }
+        assert!(result.critical() && result.fumble());
+
+        let result = rolls[1].roll_with(&mut rng);
+        for r in result.rolls() {
+            let quality = match r.value {
+                ..=5 => RollQuality::Good,
+                96.. => RollQuality::Bad,
+                _ => RollQuality::Regular,
+            };
+            assert_eq!(r.quality, quality);
+        }
+
+        let rolls = StandardNotation::parse_from_str("2d1 1d1cf1 2d20cs>21").unwrap();
+        let result = rolls[0].roll();
+        assert!(result.critical() && !result.fumble());
+        let result = rolls[1].roll();
+        assert!(!result.critical() && result.fumble());
+        assert!(!rolls[2].roll().critical());
+
+        // Successes are not critical successes, only the highest face is.
+        let rolls = StandardNotation::parse_from_str("10d10>8").unwrap();
+        for _ in 0..50 {
+            let result = rolls[0].roll_with(&mut rng);
+            let values = result.rolls().map(|r| r.value).collect::<Vec<_>>();
+            assert_eq!(result.critical(), values.contains(&10));
+            assert_eq!(result.fumble(), values.contains(&1));
+        }
+    }
+
+    #[test]
+    pub fn keeps_group_members() {
+        colored::control::set_override(false);
+        let rolls = StandardNotation::parse_from_str("{4d6, 3d8}kh1 {1d20+5,1d20+5}l1").unwrap();
+        for r in rolls.iter() {
+            let result = r.roll_with(&mut StdRng::seed_from_u64(17));
+            assert_eq!(result.groups.len(), 1);
+
+            let group = &result.groups[0];
+            assert_eq!(group.members.len(), 2);
+            let kept = group
+                .members
+                .iter()
+                .filter(|m| m.retained)
+                .collect::<Vec<_>>();
+            assert_eq!(kept.len(), 1);
+            assert_eq!(group.total, kept[0].total);
+            assert_eq!(result.total, kept[0].total);
+
+            // Only the dice of the kept member remain retained.
+            for (member, term) in group.members.iter().zip(result.terms.iter()) {
+                assert!(member.input.starts_with(&term.input));
+                assert!(term.rolls.iter().all(|r| r.retained == member.retained));
+            }
+            assert!(result.to_string().contains('{'));
+        }
+
+        let totals = rolls[1].roll().groups[0]
+            .members
+            .iter()
+            .map(|m| m.total)
+            .collect::<Vec<isize>>();
+        assert!(totals.iter().all(|t| (6..=25).contains(t)));
+    }
+
+    #[test]
+    pub fn repeats_rolls() {
+        let repeats = StandardNotation::parse_repeats("6#4d6kh3 REPEAT( 2 , 1d20+5 ) 1d8").unwrap();
+        assert_eq!(
+            repeats.iter().map(|r| r.count).collect::<Vec<usize>>(),
+            [6, 2, 1]
+        );
+        assert_eq!(
+            repeats
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<String>>(),
+            ["6#4d6h3", "2#1d20+5", "1d8"]
+        );
+
+        let results = repeats[0]
+            .try_roll_with(&mut StdRng::seed_from_u64(18))
+            .unwrap();
+        assert_eq!(results.len(), 6);
+        assert!(results.iter().all(|r| r.input == "4d6h3"));
+        assert!(results.windows(2).any(|w| w[0] != w[1]));
+
+        let rolls = StandardNotation::parse_from_str("2#1d20+5 adv repeat(2, 1d20 dis)").unwrap();
+        assert_eq!(
+            rolls.iter().map(|r| r.to_string()).collect::<Vec<String>>(),
+            ["2d20h1+5", "2d20h1+5", "2d20l1", "2d20l1"]
+        );
+
+        let rolls = StandardNotation::parse_from_str("6#4d6kh3 1d8").unwrap();
+        assert_eq!(rolls.len(), 7);
+        assert!("6#4d6".parse::<RollExpression>().is_err());
+    }
+
+    #[test]
+    pub fn tallies_symbolic_faces() {
+        colored::control::set_override(false);
+        let rolls = StandardNotation::parse_from_str("6d{hit,hit,miss,crit}").unwrap();
+        let result = rolls[0].roll_with(&mut StdRng::seed_from_u64(11));
+        assert_eq!(result.total, 0);
+        assert_eq!(result.tally().values().sum::<usize>(), 6);
+        assert!(result
+            .rolls()
+            .all(|r| ["hit", "miss", "crit"].contains(&r.symbol.as_deref().unwrap())));
+
+        let shown = result.to_string();
+        for (symbol, n) in result.tally() {
+            assert!(shown.contains(&format!("{} {}", n, symbol)), "{}", shown);
+        }
+
+        let rolls = StandardNotation::parse_from_str("10d{2,4} 5d[-2..-1]").unwrap();
+        let result = rolls[0].roll();
+        assert!(result.rolls().all(|r| [2, 4].contains(&r.value)));
+        assert!(result.tally().is_empty());
+        assert!(rolls[1]
+            .roll()
+            .rolls()
+            .all(|r| (-2..=-1).contains(&r.value)));
+    }
+
+    #[test]
+    pub fn rejects_invalid_expressions() {
+        for (input, error) in [
+            (
+                "4d6h5",
+                RollError::RetainTooMany {
+                    retain: 5,
+                    count: 4,
+                },
+            ),
+            (
+                "d6!1",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtLeast(1),
+                    faces: Faces::Standard(6),
+                },
+            ),
+            (
+                "d1!",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtLeast(1),
+                    faces: Faces::Standard(1),
+                },
+            ),
+            (
+                "d6!<6",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtMost(6),
+                    faces: Faces::Standard(6),
+                },
+            ),
+            (
+                "d6!=7",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::Equal(7),
+                    faces: Faces::Standard(6),
+                },
+            ),
+            (
+                "3d6km4",
+                RollError::RetainTooMany {
+                    retain: 4,
+                    count: 3,
+                },
+            ),
+            ("3d6dl3", RollError::DropTooMany { drop: 3, count: 3 }),
+            ("2d8/0", RollError::DivideByZero),
+            (
+                "{1d6,1d8}h3",
+                RollError::RetainTooMany {
+                    retain: 3,
+                    count: 2,
+                },
+            ),
+            ("5d10f1", RollError::NoSuccessTarget),
+            (
+                "d1r1",
+                RollError::RerollsEveryFace {
+                    faces: Faces::Standard(1),
+                },
+            ),
+            (
+                "d6r<3r>4",
+                RollError::RerollsEveryFace {
+                    faces: Faces::Standard(6),
+                },
+            ),
+            (
+                "1d6r<4!>4",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtLeast(4),
+                    faces: Faces::Standard(6),
+                },
+            ),
+            (
+                "1d6r>5!>5",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtLeast(5),
+                    faces: Faces::Standard(6),
+                },
+            ),
+            ("d[3..1]", RollError::NoFaces),
+            (
+                "d{hit,miss}!",
+                RollError::ExplodeOutOfRange {
+                    compare: ComparePoint::AtLeast(0),
+                    faces: Faces::Custom(vec![
+                        Face::Symbol("hit".to_string()),
+                        Face::Symbol("miss".to_string()),
+                    ]),
+                },
+            ),
+            (
+                "20000d6",
+                RollError::TooManyDice {
+                    count: 20000,
+                    max: MAX_DICE,
+                },
+            ),
+        ] {
+            match StandardNotation::parse_from_str(input) {
+                Err(Error::Roll(e)) => assert_eq!(e, error, "{}", input),
+                res => panic!("{} should be invalid, got {:?}", input, res),
+            }
+        }
+
+        assert!(matches!(
+            StandardNotation::parse_from_str("d99999999999999999999"),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            StandardNotation::parse_from_str("4d6h3l1"),
+            Err(Error::Parse(_))
+        ));
+        assert!(matches!(
+            StandardNotation::parse_from_str("1001#1d6"),
+            Err(Error::Roll(RollError::TooManyRepeats { count: 1001, .. }))
+        ));
+        assert!(StandardNotation::parse_from_str("d6ro<6 d6r<2r>5 d{1,1,2}r1").is_ok());
+    }
+
+    #[test]
+    pub fn reports_evaluation_errors() {
+        let rolls = StandardNotation::parse_from_str("6/(1d1-1)").unwrap();
+        assert_eq!(rolls[0].try_roll().unwrap_err(), RollError::DivideByZero);
+
+        let rolls = StandardNotation::parse_from_str("9223372036854775807+1").unwrap();
+        assert_eq!(rolls[0].try_roll().unwrap_err(), RollError::Overflow);
+    }
+
+    #[test]
+    pub fn rolls_nested_dice() {
+        let roll: RollExpression = "(1d4)d6".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..20 {
+            let result = roll.try_roll_with(&mut rng).unwrap();
+            assert_eq!(result.terms.len(), 2);
+            assert_eq!(result.nested.len(), 1);
+            let count = result.terms[0].total;
+            assert_eq!(result.nested[0].count as isize, count);
+            assert_eq!(result.nested[0].input, "(1d4)d6");
+            assert_eq!(result.terms[1].rolls.len() as isize, count);
+            assert_eq!(result.terms[1].input, format!("{}d6", count));
+        }
+
+        let roll: RollExpression = "1d(1d1*4)!".parse().unwrap();
+        let result = roll.try_roll_with(&mut rng).unwrap();
+        assert_eq!(result.nested[0].faces, Faces::Standard(4));
+        assert_eq!(
+            result.terms[1].input, "1d4!",
+            "a plain explosion targets the rolled sides"
+        );
+
+        for input in ["(1d1-1)d6", "(1d1-2)d6kh1", "(1d1-1)d6>3"] {
+            let roll: RollExpression = input.parse().unwrap();
+            let result = roll.try_roll().unwrap();
+            assert_eq!(result.total, 0, "{}", input);
+            assert_eq!(result.nested[0].count, 0);
+            assert!(result.terms[1].rolls.is_empty());
+        }
+        let roll: RollExpression = "2d(1d1-1)".parse().unwrap();
+        assert_eq!(roll.try_roll().unwrap_err(), RollError::NoFaces);
+        let roll: RollExpression = "(1d1)d6kh2".parse().unwrap();
+        assert!(matches!(
+            roll.try_roll(),
+            Err(RollError::RetainTooMany { .. })
+        ));
+    }
+
+    #[test]
+    pub fn parses_deep_nesting_quickly() {
+        let depth = MAX_NESTING;
+        let input = format!("{}1d6{}", "(".repeat(depth), ")".repeat(depth));
+        let start = std::time::Instant::now();
+        let roll: RollExpression = input.parse().unwrap();
+        assert_eq!(roll.to_string(), "1d6");
+        let input = format!("{}1d6{}d6", "(".repeat(depth), ")".repeat(depth));
+        assert!(input.parse::<RollExpression>().is_ok());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        for input in [
+            format!("{}1{}", "(".repeat(20_000), ")".repeat(20_000)),
+            format!("{}1{}", "{".repeat(depth + 1), "}".repeat(depth + 1)),
+        ] {
+            let Err(Error::Parse(e)) = input.parse::<RollExpression>() else {
+                panic!("expected a parse error");
+            };
+            assert_eq!(e.span, depth..depth + 1);
         }
     }
+
+    #[test]
+    pub fn rolls_only_the_chosen_branch() {
+        let roll: RollExpression = "if(1d20+5>=15, 2d6, 1d4)".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(22);
+        let mut passed = [false, false];
+        for _ in 0..50 {
+            let result = roll.try_roll_with(&mut rng).unwrap();
+            let condition = &result.conditions[0];
+            assert_eq!(result.terms.len(), 2);
+            assert_eq!(condition.total, result.terms[0].total + 5);
+            assert_eq!(condition.compared, Some((Comparison::AtLeast, 15)));
+            assert_eq!(condition.passed, condition.total >= 15);
+            let branch = if condition.passed { "2d6" } else { "1d4" };
+            assert_eq!(condition.branch, branch);
+            assert_eq!(result.terms[1].input, branch);
+            passed[condition.passed as usize] = true;
+        }
+        assert_eq!(passed, [true, true]);
+
+        for (input, total) in [
+            ("if(1d1>=1, 10, 20)", 10),
+            ("if(1d1>1, 10, 20)", 20),
+            ("if(1d1!=1, 10, 20)", 20),
+            ("if((1d1>1), 10, 20)", 10),
+            ("if(1d1+0>1, 10, 20)", 20),
+            ("if(1d1-1, 10, 20)", 20),
+            ("if(2<1d1+1, 10, 20)", 20),
+            ("if(2<=1d1+1, 10, 20)", 10),
+            ("if(1d1==1, if(0, 1, 2), 3)", 2),
+        ] {
+            let roll: RollExpression = input.parse().unwrap();
+            assert_eq!(roll.roll().total, total, "{}", input);
+        }
+
+        let roll: RollExpression = "if(1, 2, 6/(1d1-1))".parse().unwrap();
+        assert_eq!(roll.roll().total, 2);
+    }
+
+    #[test]
+    pub fn compares_dice_in_conditions() {
+        let d6 = || {
+            Box::new(RollExpression::Dice(Dice {
+                faces: Faces::Standard(6),
+                count: 1,
+                retention: RollRetention::All,
+                modifiers: Vec::new(),
+            }))
+        };
+        for (input, condition, comparison) in [
+            ("if(1d6!=6,1,0)", d6(), Comparison::NotEqual),
+            ("if(1d6 != 6,1,0)", d6(), Comparison::NotEqual),
+            ("if(1d6>6,1,0)", d6(), Comparison::Greater),
+            ("if(1d6 > 6,1,0)", d6(), Comparison::Greater),
+            ("if(1d6>=6,1,0)", d6(), Comparison::AtLeast),
+            ("if(1d6=6,1,0)", d6(), Comparison::Equal),
+            ("if(1d6<6,1,0)", d6(), Comparison::Less),
+        ] {
+            let RollExpression::If(conditional) = input.parse().unwrap() else {
+                panic!("{} is not a conditional", input);
+            };
+            assert_eq!(conditional.condition, condition, "{}", input);
+            assert_eq!(
+                conditional.comparison,
+                Some((comparison, Box::new(RollExpression::Constant(6)))),
+                "{}",
+                input
+            );
+        }
+
+        let RollExpression::If(conditional) = "if((3d6>5)>=2,1,0)".parse().unwrap() else {
+            panic!("not a conditional");
+        };
+        let RollExpression::Dice(dice) = conditional.condition.as_ref() else {
+            panic!("not dice");
+        };
+        assert_eq!(
+            dice.modifiers,
+            [RollModifier::Success(ComparePoint::AtLeast(5))]
+        );
+    }
+
+    #[test]
+    pub fn rejects_wrong_function_arguments() {
+        for (input, count) in [("floor(1,2)", 2), ("abs(1d6,1d4,3)", 3)] {
+            let err = StandardNotation::parse_from_str(input).unwrap_err();
+            assert!(
+                matches!(
+                    err,
+                    Error::Roll(RollError::WrongArguments { count: c, .. }) if c == count
+                ),
+                "{}",
+                input
+            );
+        }
+
+        let max = RollExpression::Function(Function::Max, vec![]);
+        assert_eq!(
+            max.try_roll().unwrap_err().to_string(),
+            "max takes at least one argument"
+        );
+        let err = "ceil(1d6/(1d1-1))".parse::<RollExpression>().unwrap();
+        assert_eq!(err.try_roll().unwrap_err(), RollError::DivideByZero);
+    }
+
+    #[test]
+    pub fn binds_variables() {
+        let roll: RollExpression = "1d1+@str+@saves.dex".parse().unwrap();
+        assert_eq!(roll.to_string(), "1d1+@str+@saves.dex");
+
+        let env = Environment::from([("str".to_string(), 3), ("saves.dex".to_string(), -1)]);
+        let result = roll
+            .try_roll_in(&env, &mut StdRng::seed_from_u64(19))
+            .unwrap();
+        assert_eq!(result.input, "1d1+@str+@saves.dex");
+        assert_eq!(result.total, 3);
+        assert_eq!(roll.bind(&env).unwrap().to_string(), "1d1+3+(-1)");
+
+        assert_eq!(
+            roll.try_roll().unwrap_err(),
+            RollError::UnboundVariable("str".to_string())
+        );
+        assert_eq!(
+            roll.bind(&Environment::new()).unwrap_err(),
+            RollError::UnboundVariable("str".to_string())
+        );
+    }
+
+    #[test]
+    pub fn parses_expressions_as_single_rolls() {
+        for input in ["2d6 + 1d4 + 3", "(1d8+2)*2", "1d8+1d6+4"] {
+            let rolls = StandardNotation::parse_from_str(input).unwrap();
+            assert_eq!(rolls.len(), 1);
+        }
+
+        let rolls = StandardNotation::parse_from_str("2d6 1d4").unwrap();
+        assert_eq!(rolls.len(), 2);
+    }
+
+    #[test]
+    pub fn respects_precedence_and_grouping() {
+        for (input, total) in [
+            ("2+3*4", 14),
+            ("(2+3)*4", 20),
+            ("10-4-3", 3),
+            ("12/2/3", 2),
+            ("-3+1", -2),
+            ("-(3+1)", -4),
+            ("7/2", 3),
+            ("-7/2", -4),
+            ("floor(-7/2)", -4),
+            ("ceil(7/2)", 4),
+            ("ceil(-7/2)", -3),
+            ("round(5/2)", 3),
+            ("round(-5/2)", -3),
+            ("round(7/3)", 2),
+            ("ceil(7/2*3)", 11),
+            ("ceil(7/2)*3", 12),
+            ("ceil(1/2+1/2)", 1),
+            ("abs(3-10)", 7),
+            ("min(4, 2*3, 5)", 4),
+            ("max(1, 2-8)", 1),
+        ] {
+            let rolls = StandardNotation::parse_from_str(input).unwrap();
+            assert_eq!(rolls[0].roll().total, total);
+        }
+    }
+
+    #[test]
+    pub fn keeps_per_term_breakdown() {
+        let rolls = StandardNotation::parse_from_str("1d8+1d6+4").unwrap();
+        let result = rolls[0].roll();
+        assert_eq!(result.input, "1d8+1d6+4");
+        assert_eq!(result.terms.len(), 2);
+        assert_eq!(
+            result.total,
+            result.terms.iter().map(|t| t.total).sum::<isize>() + 4
+        );
+    }
 }
@@ -0,0 +1,244 @@
+//! Dice pools for the World of Darkness and Chronicles of Darkness, written `7wod` for
+//! seven dice, `7wod8` for 8-again, or `7wod8r` for a rote action.
+//!
+//! Pools are rolled as d10s counting successes on 8 or more, so each die in the
+//! [`RollResult`] counts one success when it succeeded and is marked
+//! [`DiscardReason::Rerolled`](crate::DiscardReason::Rerolled) when rote rerolled it.
+
+use crate::{
+    ComparePoint, Dice, Explosion, Faces, RollError, RollExpression, RollModifier, RollResult,
+    RollRetention, TryRoll,
+};
+use rand::Rng;
+use std::fmt::Display;
+
+/// The lowest face that counts as a success.
+const SUCCESS: isize = 8;
+
+/// How many successes make an exceptional success.
+const EXCEPTIONAL: isize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pool {
+    /// The number of dice, where a pool of none rolls a single chance die.
+    pub dice: usize,
+    /// Dice showing this or more are rolled again, such as 9 for 9-again.
+    pub again: isize,
+    /// Whether failed dice are rerolled once.
+    pub rote: bool,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        Pool {
+            dice: 1,
+            again: 10,
+            rote: false,
+        }
+    }
+}
+
+/// Writes the pool in standard notation, leaving out the usual 10-again.
+impl Display for Pool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}wod", self.dice)?;
+        if self.again != 10 {
+            write!(f, "{}", self.again)?;
+        }
+        if self.rote {
+            write!(f, "r")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    /// A chance die that rolled a 1.
+    DramaticFailure,
+    Failure,
+    Success,
+    /// Five or more successes.
+    ExceptionalSuccess,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let outcome = match self {
+            Outcome::DramaticFailure => "dramatic failure",
+            Outcome::Failure => "failure",
+            Outcome::Success => "success",
+            Outcome::ExceptionalSuccess => "exceptional success",
+        };
+        write!(f, "{}", outcome)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolResult {
+    /// Every die rolled, with the number of successes as the total.
+    pub roll: RollResult,
+    pub outcome: Outcome,
+}
+
+impl Display for PoolResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.roll, self.outcome)
+    }
+}
+
+impl Pool {
+    /// The dice rolled for the pool. A chance die succeeds only on a 10, is never rolled
+    /// again, and fails dramatically on a 1.
+    pub fn dice(&self) -> Dice {
+        if self.dice == 0 {
+            return Dice {
+                faces: Faces::Standard(10),
+                count: 1,
+                retention: RollRetention::All,
+                modifiers: vec![
+                    RollModifier::Success(ComparePoint::AtLeast(10)),
+                    RollModifier::CriticalFailure(ComparePoint::Equal(1)),
+                ],
+            };
+        }
+
+        let mut modifiers = vec![RollModifier::Explode {
+            compare: ComparePoint::AtLeast(self.again),
+            kind: Explosion::Standard,
+            limit: None,
+        }];
+        if self.rote {
+            modifiers.push(RollModifier::Reroll {
+                compare: ComparePoint::AtMost(SUCCESS - 1),
+                once: true,
+            });
+        }
+        modifiers.push(RollModifier::Success(ComparePoint::AtLeast(SUCCESS)));
+
+        Dice {
+            faces: Faces::Standard(10),
+            count: self.dice,
+            retention: RollRetention::All,
+            modifiers,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), RollError> {
+        if self.dice == 0 && (self.again != 10 || self.rote) {
+            return Err(RollError::ChanceDie);
+        }
+        self.dice().validate()
+    }
+
+    pub fn try_roll(&self) -> Result<PoolResult, RollError> {
+        self.try_roll_with(&mut rand::thread_rng())
+    }
+
+    pub fn try_roll_with<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<PoolResult, RollError> {
+        let mut roll = RollExpression::Dice(self.dice()).try_roll_with(rng)?;
+        roll.input = self.to_string();
+
+        let successes = roll.successes().unwrap_or(0);
+        let outcome = if self.dice == 0 && roll.fumble() {
+            Outcome::DramaticFailure
+        } else if successes >= EXCEPTIONAL {
+            Outcome::ExceptionalSuccess
+        } else if successes > 0 {
+            Outcome::Success
+        } else {
+            Outcome::Failure
+        };
+
+        Ok(PoolResult { roll, outcome })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{DiscardReason, Error};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    pub fn counts_successes_and_rolls_again() {
+        let pool: Pool = "12wod8".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(24);
+        let mut exceptional = false;
+        for _ in 0..50 {
+            let result = pool.try_roll_with(&mut rng).unwrap();
+            let rolls = result.roll.rolls().collect::<Vec<_>>();
+            let successes = rolls.iter().filter(|r| r.value >= 8).count() as isize;
+            assert_eq!(
+                rolls.len() as isize,
+                12 + successes,
+                "every success rolls again"
+            );
+            assert_eq!(result.roll.total, successes);
+            assert!(rolls
+                .iter()
+                .all(|r| r.successes == Some((r.value >= 8) as isize)));
+            assert_eq!(
+                result.outcome == Outcome::ExceptionalSuccess,
+                successes >= 5
+            );
+            exceptional |= successes >= 5;
+        }
+        assert!(exceptional);
+    }
+
+    #[test]
+    pub fn rerolls_failures_once_on_a_rote() {
+        let pool: Pool = "5wodr".parse().unwrap();
+        let result = pool.try_roll_with(&mut StdRng::seed_from_u64(24)).unwrap();
+        let rerolled = result
+            .roll
+            .rolls()
+            .filter(|r| r.reason == Some(DiscardReason::Rerolled))
+            .collect::<Vec<_>>();
+        assert!(!rerolled.is_empty());
+        assert!(rerolled.iter().all(|r| r.value < 8 && !r.retained));
+    }
+
+    #[test]
+    pub fn rolls_a_chance_die() {
+        let pool: Pool = "0wod".parse().unwrap();
+        let mut rng = StdRng::seed_from_u64(24);
+        let mut outcomes = Vec::new();
+        for _ in 0..100 {
+            let result = pool.try_roll_with(&mut rng).unwrap();
+            let value = result.roll.terms[0].rolls[0].value;
+            assert_eq!(result.roll.rolls().count(), 1);
+            let outcome = match value {
+                1 => Outcome::DramaticFailure,
+                10 => Outcome::Success,
+                _ => Outcome::Failure,
+            };
+            assert_eq!(result.outcome, outcome);
+            outcomes.push(outcome);
+        }
+        assert!(outcomes.contains(&Outcome::DramaticFailure));
+        assert!(outcomes.contains(&Outcome::Success));
+    }
+
+    #[test]
+    pub fn round_trips_through_display() {
+        for input in ["7wod", "7wod8", "3wod9r", "0wod", "10wodr"] {
+            let pool: Pool = input.parse().unwrap();
+            assert_eq!(pool.to_string(), input);
+        }
+        assert_eq!("7WOD10".parse::<Pool>().unwrap().to_string(), "7wod");
+        assert!("wod".parse::<Pool>().is_err());
+        assert!("7wod1".parse::<Pool>().is_err());
+        assert!("7wod8+1".parse::<Pool>().is_err());
+        for input in ["0wodr", "0wod8"] {
+            assert!(matches!(
+                input.parse::<Pool>(),
+                Err(Error::Roll(RollError::ChanceDie))
+            ));
+        }
+    }
+}